@@ -1,7 +1,15 @@
-use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+
+use crate::config::read_config_file;
+use crate::provider::ProviderKind;
+use crate::template::load_templates;
 
 #[derive(Debug, Parser)]
 #[command(
+    name = "tilb",
     version,
     about,
     long_about = "A cli that handles multiple github accounts",
@@ -10,37 +18,417 @@ use clap::{Args, Parser, Subcommand};
 pub struct TilbArgs {
     #[clap(subcommand, name = "action")]
     pub action: Actions,
+    /// Show the files, git config and remotes a command would change, without writing anything
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Read and write users and rules in this file instead of config.toml in the tilb home
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
 }
 
 #[derive(Debug, Subcommand)]
 #[command(about, rename_all = "kebab-case")]
 pub enum Actions {
     /// List all users
-    List,
+    List(ListArgs),
     /// Add a new user
     Add(NewUserArgs),
     /// Remove an existing user
     Remove(UserArgs),
     /// Switch to a different user
-    Switch(UserArgs),
+    Switch(SwitchArgs),
+    /// Edit an existing user
+    Edit(UserArgs),
+    /// Clone a repo as a given user
+    Clone(CloneArgs),
+    /// Create a new repo in the current directory for a given user
+    Init(InitArgs),
+    /// Switch every repo in a directory to a given user
+    Apply(ApplyArgs),
+    /// Run a single command as a given user without changing any config
+    Exec(ExecArgs),
+    /// Print shell exports that act as a given user for the session
+    Env(EnvArgs),
+    /// Block commits and pushes made as the wrong user
+    Guard(GuardArgs),
+    /// Check, locate or edit the config file
+    Config(ConfigArgs),
+    /// List the shared profile templates or re-apply them to users
+    Template(TemplateArgs),
+    /// Show a user's public key, upload it to their account or list the keys there
+    Key(KeyArgs),
+    /// Check which account a user's ssh key logs in as
+    Verify(UserArgs),
+    /// Write users, rules and optionally keys to a bundle for another machine
+    Export(ExportArgs),
+    /// Add the users, rules and keys from a bundle made by `tilb export`
+    Import(ImportArgs),
+    /// Put back the remote and git config the current repo had before tilb switched it
+    Reset,
+    /// Revert the last change tilb made
+    Undo(UndoArgs),
+    /// List the changes tilb made, newest first
+    History,
+    /// Print the shell completion script
+    Completions(CompletionsArgs),
+    /// Print or write the manual pages
+    Man(ManArgs),
 }
 
 #[derive(Debug, Args)]
 pub struct NewUserArgs {
-    /// The username to operate on
+    /// The account's username on the git host
     pub user: String,
     /// The email shown in commits
     pub email: String,
+    /// The name shown in commits, e.g. "Jane Doe", defaults to the username
+    #[arg(long, value_name = "NAME")]
+    pub author: Option<String>,
     /// The website of the git host, e.g. github.com, gitlab.com, bitbucket.org
     #[arg(long, short, default_value_t = String::from("github.com"))]
     pub website: String,
     /// Use https instead of ssh
     #[arg(long, short, default_value_t = false)]
     pub use_https: bool,
+    /// Sign commits and tags with the generated ssh key
+    #[arg(long, default_value_t = false, conflicts_with = "use_https")]
+    pub sign: bool,
+    /// Take the host, transport, signing, ssh options and rules from a template
+    #[arg(
+        long,
+        short,
+        conflicts_with_all = ["website", "use_https", "sign"],
+        add = ArgValueCandidates::new(template_candidates),
+    )]
+    pub template: Option<String>,
+    /// Put the generated public key on the account through the host's API
+    #[arg(long, conflicts_with = "use_https")]
+    pub upload: bool,
+    /// Copy the generated public key to the clipboard
+    #[arg(long, conflicts_with = "use_https")]
+    pub copy: bool,
+    #[command(flatten)]
+    pub provider: ProviderArgs,
+}
+
+/// Which API to talk to, guessed from the user's host when left out
+#[derive(Debug, Args)]
+pub struct ProviderArgs {
+    /// The host's API flavor, needed for self-hosted instances
+    #[arg(long, value_enum)]
+    pub provider: Option<ProviderKind>,
+    /// The API's base URL, e.g. https://git.acme.com/api/v4
+    #[arg(long, value_name = "URL")]
+    pub api_url: Option<String>,
 }
 
 #[derive(Debug, Args)]
 pub struct UserArgs {
-    /// The username to operate on
+    /// The username to operate on, picked interactively if omitted
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SwitchArgs {
+    /// The username to switch to, picked interactively if omitted, `-` for the previous global user
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: Option<String>,
+    /// Change the machine-wide default user instead of the current repo
+    #[arg(long, short, conflicts_with = "auto")]
+    pub global: bool,
+    /// Pick the user from the rules in config.toml
+    #[arg(long, short, conflicts_with = "user")]
+    pub auto: bool,
+    /// With --auto, switch every repo under this directory instead
+    #[arg(long, short, requires = "auto", value_name = "DIR")]
+    pub recursive: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct CloneArgs {
+    /// The username to clone as
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
     pub user: String,
+    /// The repo to clone, e.g. owner/repo
+    pub repo: String,
+    /// The directory to clone into, defaults to the repo name
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// The username to create the repo as
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: String,
+    /// The owner of the remote repo, e.g. an organization, defaults to the username
+    #[arg(long, short)]
+    pub owner: Option<String>,
+    /// The name of the remote repo, defaults to the current directory name
+    #[arg(long, short)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// The username to switch the repos to
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: String,
+    /// The directory holding the repos
+    pub dir: PathBuf,
+    /// Also look for repos in subdirectories of subdirectories
+    #[arg(long, short)]
+    pub recursive: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExecArgs {
+    /// The username to run the command as
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: String,
+    /// The command to run, after `--`, e.g. -- git push
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct EnvArgs {
+    /// The username to export
+    #[arg(
+        add = ArgValueCandidates::new(profile_candidates),
+        required_unless_present = "unset"
+    )]
+    pub user: Option<String>,
+    /// The shell to print for, guessed from $SHELL if omitted
+    #[arg(long, short, value_enum)]
+    pub shell: Option<Shell>,
+    /// Print statements that undo a previous export instead
+    #[arg(long, conflicts_with = "user")]
+    pub unset: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GuardArgs {
+    #[command(subcommand)]
+    pub action: GuardActions,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(rename_all = "kebab-case")]
+pub enum GuardActions {
+    /// Install pre-commit and pre-push hooks that check the rules in config.toml
+    Install(GuardInstallArgs),
+    /// Remove the hooks again
+    Uninstall(GuardUninstallArgs),
+    /// Show which user the current repo must use
+    Status,
+    /// Run the checks for a hook, called by the hooks themselves
+    #[command(hide = true)]
+    Check(GuardCheckArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GuardInstallArgs {
+    /// Install for every repo through the global core.hooksPath
+    #[arg(long, short)]
+    pub global: bool,
+    /// Replace hooks that weren't written by tilb
+    #[arg(long, short)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GuardUninstallArgs {
+    /// Remove the global hooks and core.hooksPath
+    #[arg(long, short)]
+    pub global: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct GuardCheckArgs {
+    /// The hook being run, e.g. pre-commit
+    pub hook: String,
+    /// The arguments git passed to the hook
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub action: ConfigActions,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(rename_all = "kebab-case")]
+pub enum ConfigActions {
+    /// Report every problem in the config file, with its position
+    Check,
+    /// Print where the config file is
+    Path,
+    /// Open the config file in $EDITOR, checking it before it's saved
+    Edit,
+}
+
+#[derive(Debug, Args)]
+pub struct TemplateArgs {
+    #[command(subcommand)]
+    pub action: TemplateActions,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(rename_all = "kebab-case")]
+pub enum TemplateActions {
+    /// List the templates found at the `templates` path in config.toml
+    List,
+    /// Update users made from a template to its current values
+    Sync(TemplateSyncArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TemplateSyncArgs {
+    /// Only sync this user, every user made from a template if omitted
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct KeyArgs {
+    #[command(subcommand)]
+    pub action: KeyActions,
+}
+
+#[derive(Debug, Subcommand)]
+#[command(rename_all = "kebab-case")]
+pub enum KeyActions {
+    /// Print the user's public key with its fingerprint and randomart
+    Show(KeyShowArgs),
+    /// Add the user's public key to their account, for signing too if they sign
+    Upload(KeyRemoteArgs),
+    /// List the ssh keys on the user's account
+    List(KeyRemoteArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct KeyShowArgs {
+    /// The username whose key to show, picked interactively if omitted
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: Option<String>,
+    /// Also copy the public key to the clipboard
+    #[arg(long)]
+    pub copy: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct KeyRemoteArgs {
+    /// The username whose account to use, picked interactively if omitted
+    #[arg(add = ArgValueCandidates::new(profile_candidates))]
+    pub user: Option<String>,
+    #[command(flatten)]
+    pub provider: ProviderArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Include the ssh keys, encrypted with a passphrase you choose
+    #[arg(long)]
+    pub with_keys: bool,
+    /// Write the bundle here instead of printing it
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// The bundle written by `tilb export`
+    pub bundle: PathBuf,
+    /// What to do with users that already exist with different settings
+    #[arg(long, value_enum, default_value_t = OnConflict::Ask)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Ask for each user
+    Ask,
+    /// Keep the user as it is here
+    Keep,
+    /// Take the user from the bundle
+    Replace,
+}
+
+#[derive(Debug, Args)]
+pub struct UndoArgs {
+    /// Revert even if the files or git config were changed again since
+    #[arg(long, short)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// The output format
+    #[arg(long, short, value_enum, default_value_t = ListFormat::Plain)]
+    pub format: ListFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    Plain,
+    Table,
+    Json,
+    Toml,
+}
+
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    /// The shell to generate the script for
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+#[derive(Debug, Args)]
+pub struct ManArgs {
+    /// The subcommand to print the page for, e.g. switch, or key upload
+    pub page: Vec<String>,
+    /// Write every page into this directory instead of printing one
+    #[arg(long, short, conflicts_with = "page")]
+    pub out_dir: Option<PathBuf>,
+}
+
+fn profile_candidates() -> Vec<CompletionCandidate> {
+    let Ok(config) = read_config_file() else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<CompletionCandidate> = config
+        .users
+        .iter()
+        .map(|(key, user)| CompletionCandidate::new(key).help(Some(user.email.clone().into())))
+        .collect();
+    candidates.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+    candidates
+}
+
+fn template_candidates() -> Vec<CompletionCandidate> {
+    let Some(location) = read_config_file().ok().and_then(|config| config.templates) else {
+        return Vec::new();
+    };
+    let Ok(templates) = load_templates(&location) else {
+        return Vec::new();
+    };
+
+    templates
+        .iter()
+        .map(|(name, loaded)| {
+            CompletionCandidate::new(name).help(Some(loaded.template.git_host.clone().into()))
+        })
+        .collect()
 }
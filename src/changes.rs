@@ -0,0 +1,247 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use owo_colors::OwoColorize;
+use similar::TextDiff;
+
+use crate::journal::{FileChange, GitChange, Operation, append_operation, now};
+
+/*
+every file and git config write tilb makes goes through here, which keeps
+what each one looked like before for the journal, and lets --dry-run hold
+them back. in a dry run writes land in an in-memory overlay that later
+reads see, which lets a command that touches the same file twice
+(e.g. switch --global removing one ssh block and adding another) show up
+as a single diff against what's on disk
+*/
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static PENDING: Mutex<Pending> = Mutex::new(Pending {
+    files: BTreeMap::new(),
+    git: Vec::new(),
+    commands: Vec::new(),
+});
+
+struct Pending {
+    /// Original content on disk and the content it has now, `None` for no file
+    files: BTreeMap<PathBuf, (Option<String>, Option<String>)>,
+    git: Vec<GitChange>,
+    commands: Vec<String>,
+}
+
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+fn read_from_disk(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Like `fs::read_to_string`, but sees what a dry run has written so far
+pub fn read_to_string(path: &Path) -> Result<String> {
+    if is_dry_run()
+        && let Some((_, staged)) = PENDING.lock().unwrap().files.get(path)
+    {
+        return staged
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "removed in this dry run"));
+    }
+    fs::read_to_string(path)
+}
+
+/// Records `path` going to `content` once `apply` made it so, or without
+/// calling it in a dry run. A failed write isn't recorded
+fn stage(path: &Path, content: Option<String>, apply: impl FnOnce() -> Result<()>) -> Result<()> {
+    let mut pending = PENDING.lock().unwrap();
+    let original = match pending.files.get(path) {
+        Some(_) => None,
+        None => Some(read_from_disk(path)?),
+    };
+
+    if !is_dry_run() {
+        apply()?;
+    }
+
+    match original {
+        Some(original) => {
+            pending
+                .files
+                .insert(path.to_path_buf(), (original, content));
+        }
+        None => {
+            if let Some((_, staged)) = pending.files.get_mut(path) {
+                *staged = content;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `content` to `path`, creating parent directories as needed
+pub fn write(path: &Path, content: &str) -> Result<()> {
+    stage(path, Some(content.to_string()), || {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)
+    })
+}
+
+pub fn remove_file(path: &Path) -> Result<()> {
+    stage(path, None, || fs::remove_file(path))
+}
+
+/// Notes a git config change once it's made, or in place of making it in a
+/// dry run, so a failed write never ends up in the journal. No-op when nothing changes
+pub fn record_git(scope: &str, key: &str, before: Option<String>, after: Option<String>) {
+    if before == after {
+        return;
+    }
+    PENDING.lock().unwrap().git.push(GitChange {
+        scope: scope.to_string(),
+        key: key.to_string(),
+        before,
+        after,
+    });
+}
+
+/// Notes a command that changes things outside what's tracked here,
+/// e.g. `git clone` or `ssh-keygen`
+pub fn record_command(command: String) {
+    PENDING.lock().unwrap().commands.push(command);
+}
+
+fn print_diff(path: &Path, before: Option<&str>, after: Option<&str>) {
+    let path_str = path.display().to_string();
+    let old_header = if before.is_some() {
+        &path_str
+    } else {
+        "/dev/null"
+    };
+    let new_header = if after.is_some() {
+        &path_str
+    } else {
+        "/dev/null"
+    };
+
+    let before = before.unwrap_or_default();
+    let after = after.unwrap_or_default();
+    let diff = TextDiff::from_lines(before, after);
+    let rendered = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(old_header, new_header)
+        .to_string();
+
+    for line in rendered.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// A git config value as the journal has it for display, a multi-valued
+/// key's values (one per line there) on one line
+pub fn show_git_value(value: Option<&str>) -> String {
+    match value {
+        Some(value) => value.lines().collect::<Vec<_>>().join(", "),
+        None => "(unset)".to_string(),
+    }
+}
+
+/// Prints everything a dry run held back
+pub fn print_dry_run() {
+    let pending = PENDING.lock().unwrap();
+
+    for (path, (before, after)) in &pending.files {
+        if before != after {
+            print_diff(path, before.as_deref(), after.as_deref());
+        }
+    }
+
+    let mut scopes: Vec<&str> = Vec::new();
+    for change in &pending.git {
+        if !scopes.contains(&change.scope.as_str()) {
+            scopes.push(&change.scope);
+        }
+    }
+    for scope in scopes {
+        if scope == "global" {
+            println!("{}", "global git config".bold());
+        } else {
+            println!("{}", format!("git config in {}", scope).bold());
+        }
+        for change in pending.git.iter().filter(|c| c.scope == scope) {
+            println!(
+                "    {}: {} -> {}",
+                change.key,
+                show_git_value(change.before.as_deref()),
+                show_git_value(change.after.as_deref())
+            );
+        }
+    }
+
+    for command in &pending.commands {
+        println!("{} {}", "would run".bold(), command);
+    }
+
+    println!("\n{}", "Dry run, nothing was written.".yellow());
+}
+
+/// Forgets everything recorded so far, for housekeeping that isn't the user's doing
+pub fn discard() {
+    let mut pending = PENDING.lock().unwrap();
+    pending.files.clear();
+    pending.git.clear();
+    pending.commands.clear();
+}
+
+/// Adds what this run changed to the journal, if it changed anything
+pub fn save_journal(command: String) -> Result<()> {
+    let pending = PENDING.lock().unwrap();
+
+    let files: Vec<FileChange> = pending
+        .files
+        .iter()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(path, (before, after))| FileChange {
+            path: path.clone(),
+            before: before.clone(),
+            after: after.clone(),
+        })
+        .collect();
+
+    if files.is_empty() && pending.git.is_empty() && pending.commands.is_empty() {
+        return Ok(());
+    }
+
+    append_operation(Operation {
+        time: now(),
+        command,
+        files,
+        git: pending.git.clone(),
+        commands: pending.commands.clone(),
+    })
+}
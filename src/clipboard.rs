@@ -0,0 +1,50 @@
+use std::io::{Error, ErrorKind, Result, Write};
+use std::process::{Command, Stdio};
+
+// clipboard tools tried in order, the first one installed wins
+const TOOLS: &[(&str, &[&str])] = &[
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+    ("xsel", &["--clipboard", "--input"]),
+    ("pbcopy", &[]),
+    // wsl
+    ("clip.exe", &[]),
+];
+
+/// Puts `text` on the clipboard, returning the tool that did it
+pub fn copy_to_clipboard(text: &str) -> Result<&'static str> {
+    let mut last_error = None;
+
+    for (tool, args) in TOOLS {
+        let mut child = match Command::new(tool)
+            .args(*args)
+            .stdin(Stdio::piped())
+            // wl-copy and xclip leave a child behind holding the selection,
+            // reading its output would wait until something else is copied
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        // a tool that gives up early, e.g. wl-copy outside wayland, breaks
+        // the pipe, which only means trying the next one
+        let written = match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(text.as_bytes()),
+            None => Ok(()),
+        }; // stdin is closed here so the tool sees the end
+        let status = child.wait()?;
+        match written {
+            Ok(()) if status.success() => return Ok(tool),
+            Ok(()) => last_error = Some(Error::other(format!("{} failed with {}", tool, status))),
+            Err(err) => last_error = Some(Error::other(format!("{}: {}", tool, err))),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        Error::other("no clipboard tool found, install wl-copy, xclip, xsel or pbcopy")
+    }))
+}
@@ -1,91 +1,394 @@
 use std::{
-    collections::HashMap,
-    fs::{self, OpenOptions},
+    collections::{BTreeMap, HashMap},
     io::{Error, ErrorKind, Result},
-    path::PathBuf,
+    path::Path,
 };
 
-use directories::UserDirs;
 use serde::{Deserialize, Serialize};
+use toml::{Spanned, Table, Value};
+
+use crate::changes;
+use crate::paths::get_config_path;
+
+/*
+config.toml carries a version so its layout can change without breaking
+older files. a file without one is version 0, from before versioning.
+MIGRATIONS[n] turns version n into n + 1 on the raw toml, files are only
+rewritten in the new layout the next time tilb saves them
+*/
+
+pub const CONFIG_VERSION: u32 = 2;
+
+const MIGRATIONS: [fn(&mut Table); CONFIG_VERSION as usize] = [migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Version 0 files could be empty or lack `[users]`, which didn't parse
+fn migrate_v0_to_v1(table: &mut Table) {
+    table.entry("users").or_insert_with(|| Table::new().into());
+}
+
+/// Version 1 had a single `name` for both the account and commits, it's the
+/// account's username now and commits use `author_name` when it's set.
+/// Bundles share the users layout, so `tilb import` runs this on old ones too
+pub fn migrate_v1_to_v2(table: &mut Table) {
+    let Some(users) = table.get_mut("users").and_then(Value::as_table_mut) else {
+        return;
+    };
+    for (_, user) in users.iter_mut() {
+        let Some(user) = user.as_table_mut() else {
+            continue;
+        };
+        if !user.contains_key("username")
+            && let Some(name) = user.remove("name")
+        {
+            user.insert("username".into(), name);
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UsersConfig {
+    /// Layout version of the file, see `CONFIG_VERSION`
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
     pub users: HashMap<String, User>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// A template file or a directory of them, `~` is expanded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templates: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Where the values `tilb config check` looks at sit in the file
+#[derive(Debug, Deserialize)]
+pub struct SpannedConfig {
+    #[serde(default)]
+    pub users: BTreeMap<Spanned<String>, SpannedUser>,
+    #[serde(default)]
+    pub rules: Vec<SpannedRule>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpannedUser {
+    /// `name` before version 2, check looks at files as they are on disk
+    #[serde(alias = "name")]
+    pub username: Spanned<String>,
+    pub email: Spanned<String>,
+    pub git_host: Spanned<String>,
+    pub use_https: bool,
+    #[serde(default)]
+    pub gitconfig: BTreeMap<Spanned<String>, Value>,
+    #[serde(default)]
+    pub ssh_options: BTreeMap<Spanned<String>, Spanned<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpannedRule {
+    pub user: Spanned<String>,
+}
+
+impl Default for UsersConfig {
+    fn default() -> Self {
+        UsersConfig {
+            version: CONFIG_VERSION,
+            users: HashMap::new(),
+            rules: Vec::new(),
+            templates: None,
+        }
+    }
+}
+
+/// Binds repos to a user, by remote (e.g. github.com/acme-corp/*) and/or local path
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Rule {
+    /// Glob over host/owner or host/owner/repo of the origin remote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Glob over the repo's directory, `~` is expanded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The user repos matching this rule must use
+    pub user: String,
+    /// The template this rule came from, `tilb template sync` replaces these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct User {
-    pub name: String,
+    /// The account's username on the host, used in urls, the ssh alias and key file
+    pub username: String,
+    /// Name commits are authored as, the username if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_name: Option<String>,
     pub email: String,
+    /// Committer name when it should differ from the author's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_name: Option<String>,
+    /// Committer email when it should differ from the author's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_email: Option<String>,
     pub git_host: String,
     pub use_https: bool,
+    /// Sign commits and tags with the user's ssh key
+    #[serde(default)]
+    pub sign_commits: bool,
+    /// Branch name used by `tilb init`, git's own default if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+    /// More local git config set on switch, e.g. core.autocrlf = "input"
+    #[serde(default, skip_serializing_if = "Table::is_empty")]
+    pub gitconfig: Table,
+    /// Extra options for the user's ssh block, e.g. Port = "443"
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ssh_options: BTreeMap<String, String>,
+    /// The template the user was made from, see `tilb template sync`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+/// A `gitconfig` table as git's dotted keys, nested tables and quoted keys
+/// alike, e.g. `[url."git@host:"] insteadOf = ...` gives url.git@host:.insteadOf.
+/// Arrays have no single value and are left out, `tilb config check` flags them
+pub fn flatten_gitconfig(table: &Table) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    flatten_into(table, None, &mut entries);
+    entries
+}
+
+fn flatten_into(table: &Table, prefix: Option<&str>, entries: &mut Vec<(String, String)>) {
+    for (key, value) in table {
+        let name = match prefix {
+            Some(prefix) => format!("{}.{}", prefix, key),
+            None => key.clone(),
+        };
+        match value {
+            Value::Table(inner) => flatten_into(inner, Some(&name), entries),
+            Value::Array(_) => {}
+            Value::String(value) => entries.push((name, value.clone())),
+            other => entries.push((name, other.to_string())),
+        }
+    }
 }
 
-fn get_config_path() -> Option<PathBuf> {
-    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".tilb").join("config.toml"))
+impl User {
+    pub fn author_name(&self) -> &str {
+        self.author_name.as_deref().unwrap_or(&self.username)
+    }
+
+    pub fn committer_name(&self) -> &str {
+        self.committer_name.as_deref().unwrap_or(self.author_name())
+    }
+
+    pub fn committer_email(&self) -> &str {
+        self.committer_email.as_deref().unwrap_or(&self.email)
+    }
 }
 
 pub fn config_exists() -> bool {
-    get_config_path().map_or(false, |p| p.exists())
+    get_config_path().is_some_and(|p| p.exists())
 }
 
 pub fn create_config() -> Result<()> {
-    let path = get_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let path = get_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
     if !path.exists() {
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?; // ensure ~/.tilb exists
+        write_config_file(&UsersConfig::default())?; // also creates the tilb home
+        if !changes::is_dry_run() {
+            println!("Created config file at: {:?}", path);
         }
-        OpenOptions::new().create(true).write(true).open(&path)?;
-        println!("Created config file at: {:?}", path);
     }
 
     Ok(())
 }
 
+/// 1-based line and column of a byte offset, for pointing at errors
+pub fn locate(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+fn parse_error(path: &Path, content: &str, err: toml::de::Error) -> Error {
+    let message = match err.span() {
+        Some(span) => {
+            let (line, column) = locate(content, span.start);
+            format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                line,
+                column,
+                err.message().trim_end()
+            )
+        }
+        None => format!("{}: {}", path.display(), err.message().trim_end()),
+    };
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Brings a parsed config up to `CONFIG_VERSION`, returning whether anything changed
+fn migrate(path: &Path, table: &mut Table) -> Result<bool> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(value) => value
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: version must be a whole number", path.display()),
+                )
+            })?,
+    };
+
+    if version > CONFIG_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} is version {}, this tilb only knows up to {}, please upgrade",
+                path.display(),
+                version,
+                CONFIG_VERSION
+            ),
+        ));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(table);
+    }
+    table.insert("version".into(), (CONFIG_VERSION as i64).into());
+    Ok(version < CONFIG_VERSION)
+}
+
+/// Reads the config, a missing or empty file being a config with no users yet
 pub fn read_config_file() -> Result<UsersConfig> {
-    let path = get_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let path = get_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
-    let content = match fs::read_to_string(&path) {
+    let content = match changes::read_to_string(&path) {
         Ok(content) => content,
-        Err(err) if err.kind() == ErrorKind::NotFound => {
-            return Err(err);
-        }
-        Err(err) => {
-            return Err(err);
-        }
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(UsersConfig::default()),
+        Err(err) => return Err(err),
     };
 
-    let config: UsersConfig =
-        toml::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    parse_config(&path, &content)
+}
+
+/// Parses config.toml content, `path` is only used in error messages
+pub fn parse_config(path: &Path, content: &str) -> Result<UsersConfig> {
+    let mut table: Table = toml::from_str(content).map_err(|e| parse_error(path, content, e))?;
+    if migrate(path, &mut table)? {
+        // positions in the original file are gone after a migration
+        return UsersConfig::deserialize(table).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e.message().trim_end()),
+            )
+        });
+    }
 
-    Ok(config)
+    toml::from_str(content).map_err(|e| parse_error(path, content, e))
 }
 
-pub fn add_user(new_user: User) -> Result<()> {
-    let mut config = read_config_file().unwrap_or(UsersConfig {
-        users: HashMap::new(),
-    });
+pub fn write_config_file(config: &UsersConfig) -> Result<()> {
+    let path = get_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
-    config.users.insert(new_user.name.clone(), new_user);
+    let toml_str = toml::to_string_pretty(config).map_err(Error::other)?;
+    changes::write(&path, &toml_str)
+}
 
-    let toml_str = toml::to_string_pretty(&config).map_err(|e| Error::new(ErrorKind::Other, e))?;
+pub fn add_user(new_user: User) -> Result<()> {
+    let mut config = read_config_file()?;
+    config.version = CONFIG_VERSION;
 
-    let path = get_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
-    fs::write(path, toml_str)?;
-    Ok(())
+    config.users.insert(new_user.username.clone(), new_user);
+
+    write_config_file(&config)
 }
 
 pub fn delete_user(alias: &str) -> Result<()> {
-    let mut config = read_config_file().unwrap_or(UsersConfig {
-        users: HashMap::new(),
-    });
+    let mut config = read_config_file()?;
+    config.version = CONFIG_VERSION;
 
     config.users.remove(alias);
 
-    let toml_str = toml::to_string_pretty(&config).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    write_config_file(&config)
+}
 
-    let path = get_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
-    fs::write(path, toml_str)?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flatten(toml: &str) -> Vec<(String, String)> {
+        flatten_gitconfig(&toml.parse::<Table>().unwrap())
+    }
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dotted_and_nested_keys() {
+        assert_eq!(
+            flatten(
+                r#"
+                "core.autocrlf" = "input"
+                [pull]
+                rebase = true
+                [diff.tool]
+                prompt = false
+                "#
+            ),
+            entries(&[
+                ("core.autocrlf", "input"),
+                ("diff.tool.prompt", "false"),
+                ("pull.rebase", "true"),
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_subsections() {
+        assert_eq!(
+            flatten(
+                r#"
+                [url."git@tilb-me:"]
+                insteadOf = "git@github.com:"
+                [branch."feature/x y"]
+                remote = "origin"
+                "#
+            ),
+            entries(&[
+                ("branch.feature/x y.remote", "origin"),
+                ("url.git@tilb-me:.insteadOf", "git@github.com:"),
+            ])
+        );
+    }
+
+    #[test]
+    fn values_keep_their_type() {
+        assert_eq!(
+            flatten("[pack]\nthreads = 4\nwindowMemory = \"1g\"\n"),
+            entries(&[("pack.threads", "4"), ("pack.windowMemory", "1g")])
+        );
+    }
+
+    #[test]
+    fn arrays_are_left_out() {
+        assert_eq!(
+            flatten(
+                r#"
+                [remote.origin]
+                fetch = ["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"]
+                prune = true
+                [[includeIf]]
+                path = "x"
+                "#
+            ),
+            entries(&[("remote.origin.prune", "true")])
+        );
+    }
 }
@@ -1,8 +1,19 @@
 use log::{debug, info};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use inquire::{Text, validator::Validation};
 
+use crate::changes::{is_dry_run, record_command, record_git};
+
+/// How the journal and dry runs name the repo a local config change lands in
+fn repo_scope(repo: &Path) -> String {
+    repo.canonicalize()
+        .unwrap_or_else(|_| repo.to_path_buf())
+        .display()
+        .to_string()
+}
+
 pub fn check_cwd_is_repo() -> bool {
     match Command::new("git")
         .arg("rev-parse")
@@ -14,9 +25,89 @@ pub fn check_cwd_is_repo() -> bool {
     }
 }
 
-pub fn get_repo_name() -> Option<String> {
+pub fn get_repo_name(repo: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        // not `remote get-url`, which applies url.<base>.insteadOf rewrites
+        .args(["config", "--get", "remote.origin.url"])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+pub fn get_git_config(key: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "--get", key])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+pub fn get_global_config(key: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["config", "--global", "--get", key])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+pub fn set_global_config(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let before = get_global_config(key);
+    if is_dry_run() {
+        record_git("global", key, before, Some(value.to_string()));
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .args(["config", "--global", key, value])
+        .status()?;
+
+    if status.success() {
+        record_git("global", key, before, Some(value.to_string()));
+        Ok(())
+    } else {
+        Err(format!("Failed to set global git {}", key).into())
+    }
+}
+
+pub fn unset_global_config(key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let before = get_global_config(key);
+    if is_dry_run() {
+        record_git("global", key, before, None);
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .args(["config", "--global", "--unset", key])
+        .status()?;
+
+    // exit code 5 means the key wasn't set in the first place
+    if status.success() || status.code() == Some(5) {
+        record_git("global", key, before, None);
+        Ok(())
+    } else {
+        Err(format!("Failed to unset global git {}", key).into())
+    }
+}
+
+pub fn get_local_config(repo: &Path, key: &str) -> Option<String> {
     let output = Command::new("git")
-        .args(["remote", "get-url", "origin"])
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", "--get", key])
         .output()
         .ok()?;
     if output.status.success() {
@@ -26,6 +117,273 @@ pub fn get_repo_name() -> Option<String> {
     }
 }
 
+/// Every value of a multi-valued local key, in the order git has them
+pub fn get_local_config_all(repo: &Path, key: &str) -> Vec<String> {
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", "--get-all", key])
+        .output()
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Makes `values` the only values of a multi-valued local key, none unsets it.
+/// The journal gets them one per line
+pub fn set_local_config_all(
+    repo: &Path,
+    key: &str,
+    values: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let joined = |values: &[String]| (!values.is_empty()).then(|| values.join("\n"));
+    let before = joined(&get_local_config_all(repo, key));
+    let after = joined(values);
+    if before == after {
+        return Ok(());
+    }
+    if is_dry_run() {
+        record_git(&repo_scope(repo), key, before, after);
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", "--unset-all", key])
+        .status()?;
+    // exit code 5 means the key wasn't set in the first place
+    if !status.success() && status.code() != Some(5) {
+        return Err(format!("Failed to unset git {}", key).into());
+    }
+
+    for value in values {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["config", "--local", "--add", key, value])
+            .status()?;
+        if !status.success() {
+            // journal what did happen, so undo can put the old values back
+            let added = get_local_config_all(repo, key);
+            record_git(&repo_scope(repo), key, before, joined(&added));
+            return Err(format!("Failed to add {} to git {}", value, key).into());
+        }
+    }
+    record_git(&repo_scope(repo), key, before, after);
+    Ok(())
+}
+
+/// Keys of the local config matching `pattern`, a regex as `git config --get-regexp` takes
+pub fn get_local_config_keys(repo: &Path, pattern: &str) -> Vec<String> {
+    let Ok(output) = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", "--name-only", "--get-regexp", pattern])
+        .output()
+    else {
+        return Vec::new();
+    };
+    // exit code 1 just means nothing matched
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+pub fn set_local_config(
+    repo: &Path,
+    key: &str,
+    value: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let before = get_local_config(repo, key);
+    if is_dry_run() {
+        record_git(&repo_scope(repo), key, before, Some(value.to_string()));
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", key, value])
+        .status()?;
+
+    if status.success() {
+        record_git(&repo_scope(repo), key, before, Some(value.to_string()));
+        Ok(())
+    } else {
+        Err(format!("Failed to set git {}", key).into())
+    }
+}
+
+pub fn init_repo(
+    dir: &Path,
+    initial_branch: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    record_command(format!("git init {}", dir.display()));
+    if is_dry_run() {
+        return Ok(());
+    }
+
+    let mut command = Command::new("git");
+    command.arg("init");
+    if let Some(branch) = initial_branch {
+        command.arg(format!("--initial-branch={}", branch));
+    }
+
+    let status = command.arg(dir).status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err("Git init failed".into())
+    }
+}
+
+pub fn unset_local_config(repo: &Path, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let before = get_local_config(repo, key);
+    if is_dry_run() {
+        record_git(&repo_scope(repo), key, before, None);
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["config", "--local", "--unset", key])
+        .status()?;
+
+    // exit code 5 means the key wasn't set in the first place
+    if status.success() || status.code() == Some(5) {
+        record_git(&repo_scope(repo), key, before, None);
+        Ok(())
+    } else {
+        Err(format!("Failed to unset git {}", key).into())
+    }
+}
+
+pub fn clone_repo(url: &str, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    record_command(format!("git clone {} {}", url, dir.display()));
+    if is_dry_run() {
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .arg("clone")
+        .arg(url)
+        .arg(dir)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Git clone of {} failed", url).into())
+    }
+}
+
+pub fn get_repo_root(repo: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(PathBuf::from(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    } else {
+        None
+    }
+}
+
+pub fn get_hooks_dir() -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(PathBuf::from(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// The email the next commit would be authored with, env overrides included
+pub fn get_author_email() -> Option<String> {
+    let output = Command::new("git")
+        .args(["var", "GIT_AUTHOR_IDENT"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // e.g. Name <email> 1700000000 +0000
+    let ident = String::from_utf8_lossy(&output.stdout).to_string();
+    let start = ident.find('<')?;
+    let end = ident.rfind('>')?;
+    ident.get(start + 1..end).map(|s| s.to_string())
+}
+
+/// Committer emails of the commits in `git log <args>`
+pub fn get_commit_emails(args: &[&str]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let output = Command::new("git")
+        .args(["log", "--format=%ce"])
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+/// Git repos at or below `dir`, without looking inside repos themselves
+pub fn find_repos(dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    if dir.join(".git").exists() {
+        return vec![dir.to_path_buf()];
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    // file_type doesn't follow symlinks, so links can't send us in circles
+    let mut subdirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    subdirs.sort();
+
+    let mut found = Vec::new();
+    for subdir in subdirs {
+        if subdir.join(".git").exists() {
+            found.push(subdir);
+        } else if recursive {
+            found.extend(find_repos(&subdir, true));
+        }
+    }
+    found
+}
+
 pub fn get_repo_name_from_user() -> String {
     let validator = |input: &str| {
         if input.trim().is_empty() {
@@ -53,6 +411,20 @@ pub fn get_repo_name_from_user() -> String {
 pub fn parse_origin_url(url: &str) -> Option<(String, String)> {
     let url = url.trim().trim_end_matches(".git");
 
+    // https style: https://github.com/owner/repo, checked first since
+    // https://user@github.com/owner/repo looks like ssh style too
+    if url.contains("://") {
+        let parts: Vec<&str> = url.split('/').collect();
+        debug!("{:?}", parts);
+        // scheme, empty, host, owner, repo
+        if parts.len() >= 5 {
+            let owner = parts[parts.len() - 2].to_string();
+            let repo = parts[parts.len() - 1].to_string();
+            return (!owner.is_empty() && !repo.is_empty()).then_some((owner, repo));
+        }
+        return None;
+    }
+
     // ssh style: git@github.com:owner/repo
     if url.contains(':') && url.contains('@') {
         let after_colon = url.split_once(':')?.1;
@@ -60,25 +432,61 @@ pub fn parse_origin_url(url: &str) -> Option<(String, String)> {
         debug!("{:?}", parts);
         let owner = parts.next()?.to_string();
         let repo = parts.next()?.to_string();
-        return Some((owner, repo));
+        return (!owner.is_empty() && !repo.is_empty()).then_some((owner, repo));
     }
 
-    // https style: https://github.com/owner/repo
-    if url.contains("://") {
-        let parts: Vec<&str> = url.split('/').collect();
-        debug!("{:?}", parts);
-        if parts.len() >= 2 {
-            let owner = parts[parts.len() - 2].to_string();
-            let repo = parts[parts.len() - 1].to_string();
-            return Some((owner, repo));
-        }
+    None
+}
+
+pub fn parse_origin_host(url: &str) -> Option<String> {
+    let url = url.trim();
+
+    // url style: https://github.com/owner/repo, ssh://git@github.com:22/owner/repo
+    if let Some((_, rest)) = url.split_once("://") {
+        let authority = rest.split('/').next()?;
+        let host = authority.rsplit('@').next()?;
+        return host.split(':').next().map(|s| s.to_string());
+    }
+
+    // ssh style: git@github.com:owner/repo
+    if url.contains(':') && url.contains('@') {
+        let before_colon = url.split_once(':')?.0;
+        return before_colon
+            .split_once('@')
+            .map(|(_, host)| host.to_string());
     }
 
     None
 }
 
-pub fn set_git_remote(full_origin: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn parse_origin_user(url: &str) -> Option<String> {
+    // only url style remotes carry the account, ssh style is always git@
+    let (_, rest) = url.trim().split_once("://")?;
+    let authority = rest.split('/').next()?;
+    let (user_info, _) = authority.rsplit_once('@')?;
+    let user = user_info.split(':').next()?;
+    (!user.is_empty() && user != "git").then(|| user.to_string())
+}
+
+pub fn set_git_remote(repo: &Path, full_origin: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = repo_scope(repo);
+    let before = get_repo_name(repo);
+    let record = |before| {
+        record_git(
+            &scope,
+            "remote.origin.url",
+            before,
+            Some(full_origin.to_string()),
+        )
+    };
+    if is_dry_run() {
+        record(before);
+        return Ok(());
+    }
+
     let add_result = Command::new("git")
+        .arg("-C")
+        .arg(repo)
         .arg("remote")
         .arg("add")
         .arg("origin")
@@ -87,12 +495,15 @@ pub fn set_git_remote(full_origin: &str) -> Result<(), Box<dyn std::error::Error
 
     if add_result.status.success() {
         info!("Successfully added remote origin");
+        record(before);
         return Ok(());
     }
 
     let stderr = String::from_utf8_lossy(&add_result.stderr);
     if stderr.contains("already exists") {
         let set_result = Command::new("git")
+            .arg("-C")
+            .arg(repo)
             .arg("remote")
             .arg("set-url")
             .arg("origin")
@@ -101,6 +512,7 @@ pub fn set_git_remote(full_origin: &str) -> Result<(), Box<dyn std::error::Error
 
         if set_result.success() {
             info!("Successfully updated remote origin");
+            record(before);
             Ok(())
         } else {
             Err("Failed to set remote URL".into())
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::changes;
+
+/*
+hooks just call back into tilb, so rules changed in config.toml apply
+without reinstalling. the marker line tells our hooks apart from anyone
+else's, we never overwrite a hook without it unless forced
+*/
+
+pub const HOOK_NAMES: [&str; 2] = ["pre-commit", "pre-push"];
+const HOOK_MARKER: &str = "# tilb guard";
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn hook_script(hook: &str, tilb: &str, chain_repo_hook: bool) -> String {
+    let mut lines = vec![
+        "#!/bin/sh".to_string(),
+        format!("{}, remove with `tilb guard uninstall`", HOOK_MARKER),
+    ];
+
+    // pre-push gets the refs on stdin, which both tilb and a chained hook need
+    let reads_stdin = hook == "pre-push";
+    if reads_stdin {
+        lines.push("input=$(cat)".to_string());
+    }
+    let feed = if reads_stdin {
+        "printf '%s\\n' \"$input\" | "
+    } else {
+        ""
+    };
+
+    if chain_repo_hook {
+        lines.push(format!(
+            "{}{} guard check {} \"$@\" || exit 1",
+            feed, tilb, hook
+        ));
+        // core.hooksPath hides the repo's own hooks, so run them too
+        lines.push(format!(
+            "hook=\"$(git rev-parse --git-dir)/hooks/{}\"",
+            hook
+        ));
+        lines.push(format!(
+            "if [ -x \"$hook\" ]; then {}\"$hook\" \"$@\"; exit $?; fi",
+            feed
+        ));
+    } else {
+        lines.push(format!("{}exec {} guard check {} \"$@\"", feed, tilb, hook));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+pub fn is_tilb_hook(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.contains(HOOK_MARKER))
+}
+
+/// Writes every guard hook into `dir`, returning the paths written
+pub fn install_hooks(dir: &Path, chain_repo_hook: bool, force: bool) -> Result<Vec<PathBuf>> {
+    let tilb = std::env::current_exe()?;
+    let tilb = shell_quote(&tilb.display().to_string());
+
+    for hook in HOOK_NAMES {
+        let path = dir.join(hook);
+        if path.exists() && !force && !is_tilb_hook(&path) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists and wasn't written by tilb, use --force to replace it",
+                    path.display()
+                ),
+            ));
+        }
+    }
+
+    let mut written = Vec::new();
+    for hook in HOOK_NAMES {
+        let path = dir.join(hook);
+        changes::write(&path, &hook_script(hook, &tilb, chain_repo_hook))?;
+
+        #[cfg(unix)]
+        if !changes::is_dry_run() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Removes guard hooks from `dir`, leaving anyone else's alone
+pub fn uninstall_hooks(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for hook in HOOK_NAMES {
+        let path = dir.join(hook);
+        if is_tilb_hook(&path) {
+            changes::remove_file(&path)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
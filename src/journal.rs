@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths::get_journal_path;
+
+/*
+one entry per tilb run that changed something, holding what each file and
+git config key looked like before and after, so `tilb undo` can put the
+old values back. written straight to disk rather than through changes.rs,
+the journal itself is never journaled
+*/
+
+/// Runs older than this many entries are dropped
+const MAX_OPERATIONS: usize = 50;
+
+/// Entries hold whole files (~/.ssh/config, config.toml) before and after, so
+/// the oldest are also dropped while the journal is bigger than this
+const MAX_JOURNAL_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Journal {
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Operation {
+    /// Seconds since the unix epoch
+    pub time: u64,
+    /// The arguments tilb was run with
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub git: Vec<GitChange>,
+    /// Commands that were run and can't be undone, e.g. `git clone`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+}
+
+/// A file's content before and after, `None` when there was no file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GitChange {
+    /// `global` or the repo the key lives in
+    pub scope: String,
+    pub key: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn read_journal() -> Result<Journal> {
+    let path = get_journal_path().ok_or_else(|| Error::other("no home dir"))?;
+
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Journal::default()),
+        Err(err) => return Err(err),
+    };
+
+    toml::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write_journal_str(toml_str: &str) -> Result<()> {
+    let path = get_journal_path().ok_or_else(|| Error::other("no home dir"))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?; // ensure the tilb home exists
+    }
+
+    fs::write(path, toml_str)
+}
+
+pub fn write_journal(journal: &Journal) -> Result<()> {
+    write_journal_str(&toml::to_string_pretty(journal).map_err(Error::other)?)
+}
+
+pub fn append_operation(operation: Operation) -> Result<()> {
+    let mut journal = read_journal()?;
+    journal.operations.push(operation);
+
+    let excess = journal.operations.len().saturating_sub(MAX_OPERATIONS);
+    journal.operations.drain(..excess);
+
+    // the newest entry is always kept, however big
+    let mut toml_str = toml::to_string_pretty(&journal).map_err(Error::other)?;
+    while toml_str.len() > MAX_JOURNAL_BYTES && journal.operations.len() > 1 {
+        journal.operations.remove(0);
+        toml_str = toml::to_string_pretty(&journal).map_err(Error::other)?;
+    }
+
+    write_journal_str(&toml_str)
+}
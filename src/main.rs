@@ -1,13 +1,32 @@
 mod args;
+mod bundle;
+mod changes;
+mod clipboard;
 mod config;
 mod git;
+mod guard;
+mod journal;
+mod man;
 mod ops;
+mod paths;
+mod provider;
+mod rules;
+mod scratch;
 mod ssh;
+mod state;
+mod template;
 
 use args::TilbArgs;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
 use log::info;
-use ops::{handle_user_add, handle_user_list, handle_user_remove, handle_user_switch};
+use ops::{
+    handle_apply, handle_completions, handle_config, handle_env, handle_exec, handle_export,
+    handle_guard, handle_history, handle_import, handle_key, handle_man, handle_repo_clone,
+    handle_repo_init, handle_reset, handle_template, handle_undo, handle_user_add,
+    handle_user_edit, handle_user_list, handle_user_remove, handle_user_switch, handle_verify,
+    migrate_home,
+};
 
 fn check_git_installed() -> bool {
     match std::process::Command::new("git").arg("--version").output() {
@@ -17,20 +36,81 @@ fn check_git_installed() -> bool {
 }
 
 fn main() {
+    // ssh-keygen calling back in for a passphrase, see ssh::change_key_passphrase
+    if ssh::answer_askpass() {
+        return;
+    }
+
+    // answers the shell when it calls back in with COMPLETE=<shell>
+    CompleteEnv::with_factory(TilbArgs::command).complete();
+
     env_logger::init();
     let args = TilbArgs::parse();
+    changes::set_dry_run(args.dry_run);
+    if let Some(config) = args.config.clone() {
+        paths::set_config_override(config);
+    }
 
-    if check_git_installed() {
-        info!("Git is installed.");
-    } else {
-        eprintln!("Git is not installed. Please install Git to proceed.");
-        return;
+    // completions and man pages are only printed, so they work without git
+    if !matches!(
+        args.action,
+        args::Actions::Completions(_) | args::Actions::Man(_)
+    ) {
+        if check_git_installed() {
+            info!("Git is installed.");
+        } else {
+            eprintln!("Git is not installed. Please install Git to proceed.");
+            std::process::exit(1);
+        }
     }
 
+    // a dry run keeps reading ~/.tilb where it is, and so do commands that
+    // run from hooks or shell startup, where output would get in the way
+    let quiet = matches!(
+        args.action,
+        args::Actions::Env(_)
+            | args::Actions::Completions(_)
+            | args::Actions::Man(_)
+            | args::Actions::Guard(args::GuardArgs {
+                action: args::GuardActions::Check(_)
+            })
+    );
+    if !args.dry_run && !quiet {
+        migrate_home();
+    }
+
+    // undoing is never journaled, or a second undo would just redo
+    let journaled = !matches!(args.action, args::Actions::Undo(_) | args::Actions::History);
+    let command = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+
     match args.action {
-        args::Actions::List => handle_user_list(),
+        args::Actions::List(list_args) => handle_user_list(list_args),
         args::Actions::Add(new_user_args) => handle_user_add(new_user_args),
         args::Actions::Remove(user_args) => handle_user_remove(user_args),
-        args::Actions::Switch(user_args) => handle_user_switch(user_args),
+        args::Actions::Switch(switch_args) => handle_user_switch(switch_args),
+        args::Actions::Edit(user_args) => handle_user_edit(user_args),
+        args::Actions::Clone(clone_args) => handle_repo_clone(clone_args),
+        args::Actions::Init(init_args) => handle_repo_init(init_args),
+        args::Actions::Apply(apply_args) => handle_apply(apply_args),
+        args::Actions::Exec(exec_args) => handle_exec(exec_args),
+        args::Actions::Env(env_args) => handle_env(env_args),
+        args::Actions::Guard(guard_args) => handle_guard(guard_args),
+        args::Actions::Template(template_args) => handle_template(template_args),
+        args::Actions::Key(key_args) => handle_key(key_args),
+        args::Actions::Verify(user_args) => handle_verify(user_args),
+        args::Actions::Config(config_args) => handle_config(config_args),
+        args::Actions::Export(export_args) => handle_export(export_args),
+        args::Actions::Import(import_args) => handle_import(import_args),
+        args::Actions::Reset => handle_reset(),
+        args::Actions::Undo(undo_args) => handle_undo(undo_args),
+        args::Actions::History => handle_history(),
+        args::Actions::Completions(completions_args) => handle_completions(completions_args),
+        args::Actions::Man(man_args) => handle_man(man_args),
     };
+
+    if args.dry_run {
+        changes::print_dry_run();
+    } else if journaled && let Err(err) = changes::save_journal(command) {
+        eprintln!("Error writing journal: {}", err);
+    }
 }
@@ -0,0 +1,358 @@
+use clap::Command;
+use clap_mangen::Man;
+use clap_mangen::roff::{Roff, bold, italic, roman};
+use std::io::{Result, Write};
+use std::path::Path;
+
+// (subcommand, description, command line)
+const EXAMPLES: &[(&str, &str, &str)] = &[
+    (
+        "add",
+        "Add a work account on github.com, generating an ssh key for it",
+        "tilb add workuser workusermail@lovely.com",
+    ),
+    (
+        "add",
+        "Add a work account whose commits are authored under a full name",
+        "tilb add workuser workusermail@lovely.com --author \"Work User\"",
+    ),
+    (
+        "add",
+        "Add an account on gitlab.com that uses https instead of ssh",
+        "tilb add personaluser personaluser@lonely.com --website gitlab.com --use-https",
+    ),
+    (
+        "add",
+        "Add a work user set up the way the team's acme template says",
+        "tilb add workuser workusermail@lovely.com --template acme",
+    ),
+    (
+        "add",
+        "Add a user and put its key on the GitHub account, for signing too",
+        "TILB_TOKEN=ghp_... tilb add workuser workusermail@lovely.com --sign --upload",
+    ),
+    (
+        "key",
+        "Print the public key of workuser with its fingerprint and copy it to the clipboard",
+        "tilb key show workuser --copy",
+    ),
+    (
+        "key",
+        "Put the key of workuser on its GitHub account after adding it without --upload",
+        "tilb key upload workuser",
+    ),
+    (
+        "key",
+        "See which keys a self-hosted GitLab account has",
+        "tilb key list workuser --provider gitlab --api-url https://git.lovely.com/api/v4",
+    ),
+    (
+        "verify",
+        "Make sure the key of workuser logs in as workuser and not someone else",
+        "tilb verify workuser",
+    ),
+    ("list", "Show every configured user", "tilb list"),
+    (
+        "list",
+        "Print users as JSON for scripting",
+        "tilb list --format json",
+    ),
+    (
+        "switch",
+        "Use workuser for the repository in the current directory",
+        "tilb switch workuser",
+    ),
+    ("switch", "Pick the user from a list instead", "tilb switch"),
+    (
+        "switch",
+        "Make workuser the default for every repo on this machine",
+        "tilb switch --global workuser",
+    ),
+    (
+        "switch",
+        "Show what a global switch would change in ~/.ssh/config and the git config",
+        "tilb switch --global workuser --dry-run",
+    ),
+    (
+        "switch",
+        "Go back to the global user from before the last global switch",
+        "tilb switch --global -",
+    ),
+    (
+        "switch",
+        "Pick the user for the current repo from the rules in config.toml",
+        "tilb switch --auto",
+    ),
+    (
+        "switch",
+        "Fix every repo under ~/src according to the rules",
+        "tilb switch --auto --recursive ~/src",
+    ),
+    (
+        "config",
+        "List bad emails, missing keys and clashing ssh aliases, with their positions",
+        "tilb config check",
+    ),
+    (
+        "config",
+        "Edit users and rules in $EDITOR, checked before they're saved",
+        "tilb config edit",
+    ),
+    (
+        "template",
+        "Bring every user made from a template up to date with it",
+        "tilb template sync",
+    ),
+    (
+        "export",
+        "Bundle every user, rule and ssh key, the keys encrypted with a passphrase",
+        "tilb export --with-keys -o tilb-bundle.toml",
+    ),
+    (
+        "import",
+        "Set up the users from a bundle on a new machine, keeping users that already exist",
+        "tilb import tilb-bundle.toml --on-conflict keep",
+    ),
+    (
+        "reset",
+        "Put back the origin, identity and gitconfig keys the current repo had before tilb switched it",
+        "tilb reset",
+    ),
+    (
+        "edit",
+        "Change the email or host of workuser",
+        "tilb edit workuser",
+    ),
+    (
+        "clone",
+        "Clone a private work repo as workuser, with workuser's name and email set",
+        "tilb clone workuser acme-corp/backend",
+    ),
+    (
+        "init",
+        "Start a new repo in the current directory for the acme-corp organization",
+        "tilb init workuser --owner acme-corp",
+    ),
+    (
+        "apply",
+        "Preview switching every clone under ~/src/acme to workuser",
+        "tilb apply workuser ~/src/acme --recursive --dry-run",
+    ),
+    (
+        "exec",
+        "Push once as workuser without touching any config",
+        "tilb exec workuser -- git push",
+    ),
+    (
+        "env",
+        "Act as workuser for the rest of a bash session",
+        "eval \"$(tilb env workuser)\"",
+    ),
+    ("env", "Undo it again", "eval \"$(tilb env --unset)\""),
+    (
+        "guard",
+        "Refuse commits and pushes made as the wrong user in every repo",
+        "tilb guard install --global",
+    ),
+    (
+        "guard",
+        "See which user the current repo is bound to",
+        "tilb guard status",
+    ),
+    ("history", "See what tilb changed lately", "tilb history"),
+    (
+        "undo",
+        "Put back the git config, remote and ssh config from before the last change",
+        "tilb undo",
+    ),
+    (
+        "remove",
+        "Forget workuser and its ssh config block",
+        "tilb remove workuser",
+    ),
+    (
+        "completions",
+        "Enable completions in bash",
+        "source <(tilb completions bash)",
+    ),
+    (
+        "man",
+        "Write all manual pages to a directory",
+        "tilb man --out-dir /usr/local/share/man/man1",
+    ),
+];
+
+// (path, description)
+const FILES: &[(&str, &str)] = &[
+    (
+        "~/.config/tilb/config.toml",
+        "Users added with tilb add, and the [[rules]] binding repos to them.",
+    ),
+    (
+        "~/.config/tilb/state.toml",
+        "The current and previous global user, used by tilb switch --global -.",
+    ),
+    (
+        "~/.config/tilb/journal.toml",
+        "The last 50 changes tilb made with their old values (fewer when they add up to over 1 MiB), used by tilb undo and tilb history.",
+    ),
+    (
+        "~/.config/tilb/hooks/",
+        "Hooks installed by tilb guard install --global, used through core.hooksPath.",
+    ),
+    (
+        "~/.tilb/",
+        "Where tilb kept its files before it followed XDG, moved to ~/.config/tilb on the next run.",
+    ),
+    (
+        "~/.ssh/tilb/",
+        "Private and public keys generated for each ssh user. They are kept when a user is removed.",
+    ),
+    (
+        "~/.ssh/config",
+        "Gets one block per ssh user, each starting with a \"#tilb generated\" line. \
+         Anything outside those blocks is left untouched.",
+    ),
+];
+
+// (variable, description)
+const ENVIRONMENT: &[(&str, &str)] = &[
+    (
+        "TILB_HOME",
+        "Directory holding config.toml, state.toml, journal.toml and hooks/, instead of ~/.config/tilb.",
+    ),
+    (
+        "XDG_CONFIG_HOME",
+        "The tilb home is $XDG_CONFIG_HOME/tilb when set and TILB_HOME isn't, ~/.config/tilb otherwise.",
+    ),
+    (
+        "TILB_SSH_DIR",
+        "Directory for generated ssh keys, instead of ~/.ssh/tilb. The ssh config blocks stay in ~/.ssh/config.",
+    ),
+    (
+        "TILB_TOKEN",
+        "API token for add --upload and key upload/list, asked for when unset.",
+    ),
+];
+
+/// Whether an example belongs on the page for `path`, e.g. ["key", "upload"]
+fn example_matches(path: &[&str], name: &str, command: &str) -> bool {
+    match path {
+        [] => true,
+        [top] => *top == name,
+        [top, ..] => *top == name && command.contains(&format!("tilb {}", path.join(" "))),
+    }
+}
+
+fn render_examples(roff: &mut Roff, path: &[&str]) {
+    let examples: Vec<_> = EXAMPLES
+        .iter()
+        .filter(|(name, _, command)| example_matches(path, name, command))
+        .collect();
+    if examples.is_empty() {
+        return;
+    }
+
+    roff.control("SH", ["EXAMPLES"]);
+    for (_, description, command) in examples {
+        roff.control("TP", []);
+        roff.text([roman(*description)]);
+        roff.control("RS", []);
+        roff.text([bold(*command)]);
+        roff.control("RE", []);
+    }
+}
+
+fn render_files(roff: &mut Roff) {
+    roff.control("SH", ["FILES"]);
+    for (path, description) in FILES {
+        roff.control("TP", []);
+        roff.text([italic(*path)]);
+        roff.text([roman(*description)]);
+    }
+}
+
+fn render_environment(roff: &mut Roff) {
+    roff.control("SH", ["ENVIRONMENT"]);
+    for (name, description) in ENVIRONMENT {
+        roff.control("TP", []);
+        roff.text([bold(*name)]);
+        roff.text([roman(*description)]);
+    }
+}
+
+fn render_page(cmd: Command, path: &[&str], w: &mut dyn Write) -> Result<()> {
+    let version = cmd.get_version().map(|v| v.to_string());
+    let has_subcommands = cmd.get_subcommands().any(|s| !s.is_hide_set());
+    let man = Man::new(cmd);
+    man.render_title(w)?;
+    man.render_name_section(w)?;
+    man.render_synopsis_section(w)?;
+    man.render_description_section(w)?;
+    man.render_options_section(w)?;
+    if has_subcommands {
+        man.render_subcommands_section(w)?;
+    }
+
+    let mut roff = Roff::default();
+    render_examples(&mut roff, path);
+    render_environment(&mut roff);
+    render_files(&mut roff);
+    roff.to_writer(w)?;
+
+    if version.is_some() {
+        man.render_version_section(w)?;
+    }
+    Ok(())
+}
+
+fn build_root(cmd: Command) -> Command {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    cmd
+}
+
+/// Writes the page for `tilb` or one of its subcommands, `path` being e.g. ["key", "upload"]
+pub fn write_page(cmd: Command, path: &[String], w: &mut dyn Write) -> Result<()> {
+    let mut page = build_root(cmd);
+    for name in path {
+        page = page.find_subcommand(name).cloned().ok_or_else(|| {
+            std::io::Error::other(format!("no such subcommand: {}", path.join(" ")))
+        })?;
+    }
+
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    render_page(page, &path, w)
+}
+
+/// Every visible subcommand under `cmd`, depth first, with its path from the root
+fn collect_pages(cmd: &Command, path: &[String], pages: &mut Vec<(Command, Vec<String>)>) {
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let mut sub_path = path.to_vec();
+        sub_path.push(sub.get_name().to_string());
+        pages.push((sub.clone(), sub_path.clone()));
+        collect_pages(sub, &sub_path, pages);
+    }
+}
+
+/// Writes `tilb.1` and one `tilb-<subcommand>.1` per subcommand, nested ones
+/// included (e.g. `tilb-key-upload.1`), into `out_dir`
+pub fn write_pages(cmd: Command, out_dir: &Path) -> Result<Vec<String>> {
+    let root = build_root(cmd);
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut written = Vec::new();
+    let mut pages: Vec<(Command, Vec<String>)> = vec![(root.clone(), Vec::new())];
+    collect_pages(&root, &[], &mut pages);
+
+    for (page, path) in pages {
+        let filename = Man::new(page.clone()).get_filename();
+        let mut file = std::fs::File::create(out_dir.join(&filename))?;
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        render_page(page, &path, &mut file)?;
+        file.flush()?;
+        written.push(filename);
+    }
+
+    Ok(written)
+}
@@ -1,16 +1,55 @@
-use crate::args::{NewUserArgs, UserArgs};
-use crate::config::{User, add_user, config_exists, create_config, delete_user, read_config_file};
+use crate::args::{
+    ApplyArgs, CloneArgs, CompletionsArgs, ConfigActions, ConfigArgs, EnvArgs, ExecArgs,
+    ExportArgs, GuardActions, GuardArgs, GuardCheckArgs, GuardInstallArgs, GuardUninstallArgs,
+    ImportArgs, InitArgs, KeyActions, KeyArgs, KeyRemoteArgs, KeyShowArgs, ListArgs, ListFormat,
+    ManArgs, NewUserArgs, OnConflict, ProviderArgs, Shell, SwitchArgs, TemplateActions,
+    TemplateArgs, TilbArgs, UndoArgs, UserArgs,
+};
+use crate::bundle::{BUNDLE_VERSION, Bundle, BundledKey, bundle_to_string, read_bundle};
+use crate::changes::{self, is_dry_run};
+use crate::clipboard::copy_to_clipboard;
+use crate::config::{
+    CONFIG_VERSION, Rule, SpannedConfig, User, UsersConfig, add_user, config_exists, create_config,
+    delete_user, flatten_gitconfig, locate, parse_config, read_config_file, write_config_file,
+};
 use crate::git::{
-    check_cwd_is_repo, get_repo_name, get_repo_name_from_user, parse_origin_url, set_git_remote,
+    check_cwd_is_repo, clone_repo, find_repos, get_author_email, get_commit_emails, get_git_config,
+    get_global_config, get_hooks_dir, get_local_config, get_local_config_all,
+    get_local_config_keys, get_repo_name, get_repo_name_from_user, get_repo_root, init_repo,
+    parse_origin_host, parse_origin_url, set_git_remote, set_global_config, set_local_config,
+    set_local_config_all, unset_global_config, unset_local_config,
+};
+use crate::guard::{install_hooks, is_tilb_hook, uninstall_hooks};
+use crate::journal::{Operation, now, read_journal, write_journal};
+use crate::man::{write_page, write_pages};
+use crate::paths::{
+    get_config_path, get_global_hooks_dir, get_ignored_legacy_home, get_ssh_config_path,
+    migrate_legacy_home, rebase_path,
+};
+use crate::provider::{KeyUsage, Provider, ProviderKind, same_public_key};
+use crate::rules::{RemoteInfo, find_rule, resolve_remote};
+use crate::scratch::ScratchDir;
+use crate::ssh::{
+    Greeting, add_to_ssh_config, change_key_passphrase, check_ssh_option, generate_ssh_key,
+    get_block_options, get_host_alias, get_key_fingerprint, get_key_path, get_key_randomart,
+    parse_greeting, remove_from_ssh_config, ssh_greeting, write_secret_file,
 };
-use crate::ssh::{add_to_ssh_config, generate_ssh_key, get_ssh_dir_path, remove_from_ssh_config};
+use crate::state::{GlobalIdentity, read_state_file, write_state_file};
+use crate::template::{LoadedTemplate, Template, load_templates};
+use clap::CommandFactory;
+use clap_complete::env::{Bash, EnvCompleter, Fish, Powershell, Zsh};
 use inquire::validator::Validation;
-use inquire::{Confirm, Password, PasswordDisplayMode};
+use inquire::{Confirm, Password, PasswordDisplayMode, Select, Text};
 use log::info;
 use owo_colors::OwoColorize;
 use regex::Regex;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use toml::Table;
 
 // TODO:
 // make return properly handled
@@ -51,11 +90,317 @@ fn is_git_hosting_site(url: &str) -> bool {
         .unwrap_or(false)
 }
 
+struct UserOption {
+    key: String,
+    label: String,
+}
+
+impl fmt::Display for UserOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+fn pick_user(config: &UsersConfig, message: &str) -> Option<String> {
+    if config.users.is_empty() {
+        println!(
+            "(no users found, add a new user via `{}`)",
+            "tilb add".blue()
+        );
+        return None;
+    }
+
+    let current_name = get_git_config("user.name");
+    let current_email = get_git_config("user.email");
+    let remote_host = get_repo_name(Path::new(".")).and_then(|url| parse_origin_host(&url));
+
+    let mut users: Vec<(&String, &User)> = config.users.iter().collect();
+    users.sort_by(|a, b| a.0.cmp(b.0));
+
+    // prefer the profile whose alias is in the remote, then any profile on the same host
+    let cursor = remote_host
+        .as_ref()
+        .and_then(|host| {
+            users
+                .iter()
+                .position(|(_, user)| &get_host_alias(&user.username) == host)
+                .or_else(|| users.iter().position(|(_, user)| &user.git_host == host))
+        })
+        .unwrap_or(0);
+
+    let options: Vec<UserOption> = users
+        .iter()
+        .map(|(key, user)| {
+            let transport = if user.use_https { "https" } else { "ssh" };
+            let is_active = current_name.as_deref() == Some(user.author_name())
+                && current_email.as_deref() == Some(user.email.as_str());
+            let mut label = format!("{} <{}> {} ({})", key, user.email, user.git_host, transport);
+            if **key != user.username {
+                label.push_str(&format!(" as {}", user.username));
+            }
+            if is_active {
+                label.push_str(" [active]");
+            }
+            UserOption {
+                key: key.to_string(),
+                label,
+            }
+        })
+        .collect();
+
+    Select::new(message, options)
+        .with_starting_cursor(cursor)
+        .prompt()
+        .ok()
+        .map(|option| option.key)
+}
+
+fn resolve_user(config: &UsersConfig, user: Option<String>, message: &str) -> Option<String> {
+    match user {
+        Some(user) => Some(user),
+        None => pick_user(config, message),
+    }
+}
+
+fn load_config() -> Option<UsersConfig> {
+    match read_config_file() {
+        // stderr, `tilb env` output gets eval'd
+        Ok(config) if config.users.is_empty() => {
+            eprintln!("No users yet, add a new user via `{}`!", "tilb add".blue());
+            None
+        }
+        Ok(config) => Some(config),
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            None
+        }
+    }
+}
+
+fn get_remote_url(user: &User, owner: &str, repo: &str) -> String {
+    if user.use_https {
+        // username in the url lets credential managers pick the right account
+        format!(
+            "https://{}@{}/{}/{}",
+            user.username, user.git_host, owner, repo
+        )
+    } else {
+        format!("git@{}:{}/{}", get_host_alias(&user.username), owner, repo)
+    }
+}
+
+// set for some users only, unset again when switching to one without them
+const OPTIONAL_KEYS: [&str; 6] = [
+    "gpg.format",
+    "user.signingkey",
+    "commit.gpgsign",
+    "tag.gpgsign",
+    "committer.name",
+    "committer.email",
+];
+
+fn get_identity_config(user: &User) -> Vec<(&'static str, String)> {
+    let mut entries = vec![
+        ("user.name", user.author_name().to_string()),
+        ("user.email", user.email.clone()),
+    ];
+
+    // git commits with user.* unless committer.* says otherwise
+    if let Some(committer_name) = &user.committer_name {
+        entries.push(("committer.name", committer_name.clone()));
+    }
+    if let Some(committer_email) = &user.committer_email {
+        entries.push(("committer.email", committer_email.clone()));
+    }
+
+    if user.sign_commits
+        && let Some(key_path) = get_key_path(&user.username)
+    {
+        let public_key = key_path.with_extension("pub").display().to_string();
+        entries.push(("gpg.format", "ssh".to_string()));
+        entries.push(("user.signingkey", public_key));
+        entries.push(("commit.gpgsign", "true".to_string()));
+        entries.push(("tag.gpgsign", "true".to_string()));
+    }
+
+    entries
+}
+
+// everything tilb may set in a repo, saved as tilb.<key> before it first sets it
+const SAVED_KEYS: [&str; 9] = [
+    "user.name",
+    "user.email",
+    "committer.name",
+    "committer.email",
+    "gpg.format",
+    "user.signingkey",
+    "commit.gpgsign",
+    "tag.gpgsign",
+    "remote.origin.url",
+];
+const SAVED_MARKER: &str = "tilb.saved";
+// the SAVED_KEYS a repo has had saved, one value each, so keys added to
+// SAVED_KEYS later are still saved in repos switched before
+const SAVED_KEYS_MARKER: &str = "tilb.savedkey";
+// keys a user's gitconfig table has set in a repo, one value each, their
+// values from before are saved as tilb.<key> like the ones above
+const GITCONFIG_MARKER: &str = "tilb.gitconfig";
+// kept with `git config --add`, the journal has their values one per line
+const MULTI_VALUED_MARKERS: [&str; 2] = [SAVED_KEYS_MARKER, GITCONFIG_MARKER];
+
+/// `key` the way git spells it, section and name are case-insensitive and
+/// come out lowercased, a subsection is case-sensitive and left alone
+fn normalize_git_key(key: &str) -> String {
+    let Some((section, rest)) = key.split_once('.') else {
+        return key.to_lowercase();
+    };
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    }
+}
+
+/// The user's `gitconfig` entries, bar the keys tilb sets from the user itself
+fn get_extra_config(user: &User) -> Vec<(String, String)> {
+    flatten_gitconfig(&user.gitconfig)
+        .into_iter()
+        .filter(|(key, _)| !is_reserved_key(key))
+        .map(|(key, value)| (normalize_git_key(&key), value))
+        .collect()
+}
+
+fn is_reserved_key(key: &str) -> bool {
+    SAVED_KEYS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(key))
+}
+
+fn get_managed_keys(repo: &Path) -> Vec<String> {
+    get_local_config_all(repo, GITCONFIG_MARKER)
+        .iter()
+        .map(|key| normalize_git_key(key))
+        .collect()
+}
+
+/// Keeps what a repo had before tilb touched it, for `tilb reset`, keys that
+/// weren't set are left out and come back unset. Each key is saved once
+fn save_original_config(repo: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut saved = get_local_config_all(repo, SAVED_KEYS_MARKER);
+
+    for key in SAVED_KEYS {
+        if saved.iter().any(|done| done == key) {
+            continue;
+        }
+        if let Some(value) = get_local_config(repo, key) {
+            set_local_config(repo, &format!("tilb.{}", key), &value)?;
+        }
+        saved.push(key.to_string());
+    }
+    set_local_config_all(repo, SAVED_KEYS_MARKER, &saved)?;
+    set_local_config(repo, SAVED_MARKER, "true")
+}
+
+fn get_ssh_command(user: &User) -> Option<String> {
+    if user.use_https {
+        return None;
+    }
+
+    let key_path = get_key_path(&user.username)?;
+    // git runs this through a shell, so quote the path
+    Some(format!(
+        "ssh -i '{}' -o IdentitiesOnly=yes",
+        key_path.display().to_string().replace('\'', "'\\''")
+    ))
+}
+
+const IDENTITY_ENV_VARS: [&str; 5] = [
+    "GIT_AUTHOR_NAME",
+    "GIT_AUTHOR_EMAIL",
+    "GIT_COMMITTER_NAME",
+    "GIT_COMMITTER_EMAIL",
+    "GIT_SSH_COMMAND",
+];
+
+fn get_identity_env(user: &User) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        ("GIT_AUTHOR_NAME", user.author_name().to_string()),
+        ("GIT_AUTHOR_EMAIL", user.email.clone()),
+        ("GIT_COMMITTER_NAME", user.committer_name().to_string()),
+        ("GIT_COMMITTER_EMAIL", user.committer_email().to_string()),
+    ];
+    if let Some(ssh_command) = get_ssh_command(user) {
+        vars.push(("GIT_SSH_COMMAND", ssh_command));
+    }
+    vars
+}
+
+fn apply_user_config(repo: &Path, user: &User) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = get_identity_config(user);
+    for (key, value) in &entries {
+        set_local_config(repo, key, value)?;
+    }
+
+    // drop signing and committer overrides left behind by a previous user
+    for key in OPTIONAL_KEYS {
+        if !entries.iter().any(|(k, _)| *k == key) {
+            unset_local_config(repo, key)?;
+        }
+    }
+    apply_extra_config(repo, user)
+}
+
+/// Sets the user's gitconfig table, saving what the repo had the first time
+/// a key is set, and puts that back for keys an earlier user set and this one doesn't
+fn apply_extra_config(repo: &Path, user: &User) -> Result<(), Box<dyn std::error::Error>> {
+    let extra = get_extra_config(user);
+    let mut managed = get_managed_keys(repo);
+
+    for (key, value) in &extra {
+        if !managed.contains(key) {
+            if let Some(original) = get_local_config(repo, key) {
+                set_local_config(repo, &format!("tilb.{}", key), &original)?;
+            }
+            managed.push(key.clone());
+        }
+        set_local_config(repo, key, value)?;
+    }
+
+    for key in &managed {
+        if !extra.iter().any(|(k, _)| k == key) {
+            let original = get_local_config(repo, &format!("tilb.{}", key));
+            set_or_unset_local(repo, key, original.as_deref())?;
+        }
+    }
+
+    set_local_config_all(repo, GITCONFIG_MARKER, &managed)
+}
+
 pub fn handle_user_add(user_args: NewUserArgs) {
     let user = user_args.user;
     let email = user_args.email;
-    let website = user_args.website;
-    let use_https = user_args.use_https;
+
+    let template = match &user_args.template {
+        Some(name) => match find_template(name) {
+            Some(template) => Some(template),
+            None => return,
+        },
+        None => None,
+    };
+
+    // the template's values stand in for the flags it conflicts with
+    let (website, use_https, sign) = match &template {
+        Some(template) => (
+            template.git_host.clone(),
+            template.use_https,
+            template.sign_commits,
+        ),
+        None => (user_args.website, user_args.use_https, user_args.sign),
+    };
 
     // TODO: check if user already exists
     println!("Adding user: {}", user.green());
@@ -82,7 +427,8 @@ pub fn handle_user_add(user_args: NewUserArgs) {
         }
     }
 
-    if !is_git_hosting_site(&website) {
+    // a template's host is the team's call, e.g. a self-hosted instance
+    if template.is_none() && !is_git_hosting_site(&website) {
         eprintln!(
             "{} doesn't look like a git hosting site",
             email.bright_red()
@@ -122,6 +468,7 @@ pub fn handle_user_add(user_args: NewUserArgs) {
         }
     }
 
+    let mut public_key = None;
     if !use_https {
         let validator = |input: &str| {
             if input.contains(' ') {
@@ -133,79 +480,160 @@ pub fn handle_user_add(user_args: NewUserArgs) {
             }
         };
 
-        let passphrase = Password::new("Enter passphrase (leave empty for no passphrase):")
-            .with_display_mode(PasswordDisplayMode::Masked)
-            .with_validator(validator)
-            .prompt()
-            .expect("failed to read passphrase");
+        // a dry run generates no key, so has no use for a passphrase
+        let passphrase = if is_dry_run() {
+            String::new()
+        } else {
+            Password::new("Enter passphrase (leave empty for no passphrase):")
+                .with_display_mode(PasswordDisplayMode::Masked)
+                .with_validator(validator)
+                .prompt()
+                .expect("failed to read passphrase")
+        };
 
-        let ssh_file = format!("id_{}_ed25519", &user);
-        let ssh_path = get_ssh_dir_path()
+        let ssh_path = get_key_path(&user)
             .expect("no home dir")
-            .join(ssh_file)
             .display()
             .to_string();
 
-        let pub_content = generate_ssh_key(&user, &passphrase).expect("failed to generate ssh key");
+        let pub_content = match generate_ssh_key(&user, &passphrase) {
+            Ok(pub_content) => pub_content,
+            Err(err) => {
+                eprintln!("Error generating ssh key: {}", err);
+                return;
+            }
+        };
 
-        println!(
-            "Public key (make sure to add to {}):\n{}",
-            &domain_name, &pub_content
-        );
+        if !is_dry_run() {
+            if !user_args.upload {
+                println!("Public key (make sure to add to {}):", &domain_name);
+                print_public_key(&user, &pub_content);
+            }
+            if user_args.copy {
+                copy_public_key(&pub_content);
+            }
+        }
+        public_key = Some(pub_content);
 
         // username is being used as host alias in ssh config
         // check ssh for format
-        let host_alias = format!("tilb-{}", &user);
+        let host_alias = get_host_alias(&user);
 
-        add_to_ssh_config(&host_alias, &domain_name, &user, &ssh_path)
+        let ssh_options = template
+            .as_ref()
+            .map(|template| template.ssh_options.clone())
+            .unwrap_or_default();
+        add_to_ssh_config(&host_alias, &domain_name, "git", &ssh_path, &ssh_options)
             .expect("failed to update ssh config");
     }
 
-    let new_user = User {
-        name: user.clone(),
+    let mut new_user = User {
+        username: user.clone(),
+        author_name: user_args.author.clone(),
         email: email.clone(),
+        committer_name: None,
+        committer_email: None,
         git_host: domain_name.clone(),
-        use_https: use_https,
+        use_https,
+        sign_commits: sign,
+        default_branch: None,
+        gitconfig: Table::new(),
+        ssh_options: BTreeMap::new(),
+        template: None,
     };
+    if let (Some(template), Some(name)) = (&template, &user_args.template) {
+        template.apply_to(&mut new_user);
+        new_user.git_host = domain_name.clone();
+        new_user.template = Some(name.clone());
+    }
 
     if let Err(err) = add_user(new_user) {
         eprintln!("Error updating config: {}", err);
         return;
     }
 
-    println!("User: {} <{}> added", user.green(), email.green());
-}
-
-pub fn handle_user_remove(user: UserArgs) {
-    let user = user.user;
+    if let (Some(template), Some(name)) = (&template, &user_args.template)
+        && !template.rules.is_empty()
+    {
+        let result = read_config_file().and_then(|mut config| {
+            replace_template_rules(&mut config, &user, template.rules_for(name, &user));
+            write_config_file(&config)
+        });
+        if let Err(err) = result {
+            eprintln!("Error updating config: {}", err);
+            return;
+        }
+    }
 
-    if !config_exists() {
-        println!(
-            "Config not found, add a new user via `{}`!",
-            "tilb add".blue()
-        );
-        return;
+    if is_dry_run() {
+        println!("Would add user: {} <{}>", user.green(), email.green());
+    } else {
+        println!("User: {} <{}> added", user.green(), email.green());
     }
 
-    let config = match read_config_file() {
-        Ok(config) => config,
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            println!(
-                "Config not found, add a new user via `{}`!",
-                "tilb add".blue()
-            );
+    let mut uploaded = false;
+    if user_args.upload {
+        if is_dry_run() {
+            changes::record_command(format!(
+                "upload the public key of {} to {}",
+                user, domain_name
+            ));
             return;
         }
-        Err(err) => {
-            eprintln!("Error reading config: {}", err);
+        let Ok(config) = read_config_file() else {
+            return;
+        };
+        let (Some(added), Some(public_key)) = (config.users.get(&user), public_key) else {
             return;
+        };
+        uploaded = resolve_provider(added, &user_args.provider)
+            .is_some_and(|provider| upload_public_key(&user, added, &provider, &public_key));
+        if !uploaded {
+            println!(
+                "Public key (add it to {} by hand, or retry with `tilb key upload {}`):",
+                &domain_name, &user
+            );
+            print_public_key(&user, &public_key);
         }
+    }
+
+    if use_https || is_dry_run() {
+        return;
+    }
+    let question = if uploaded {
+        "Check which account the key logs in as?".to_string()
+    } else {
+        format!(
+            "Once the key is on your {} account, check which account it logs in as?",
+            domain_name
+        )
+    };
+    match Confirm::new(&question).with_default(true).prompt() {
+        Ok(true) => {
+            if let Ok(config) = read_config_file()
+                && let Some(added) = config.users.get(&user)
+            {
+                verify_user(&user, added);
+            }
+        }
+        _ => println!("Check it any time with `tilb verify {}`", user),
+    }
+}
+
+pub fn handle_user_remove(user_args: UserArgs) {
+    let Some(config) = load_config() else {
+        return;
     };
 
-    if config.users.get(&user).is_none() {
+    let Some(user) = resolve_user(&config, user_args.user, "Which user to remove?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+
+    let Some(username) = config.users.get(&user).map(|found| found.username.clone()) else {
         eprintln!("User '{}' not found in config.", user);
         return;
-    }
+    };
 
     println!("NOTE: The ssh key for {} will not be delete", user.green());
 
@@ -230,33 +658,82 @@ pub fn handle_user_remove(user: UserArgs) {
         return;
     }
 
-    let host_alias = format!("github-{}", &user);
+    let host_alias = get_host_alias(&username);
     remove_from_ssh_config(&host_alias).expect("failed to update ssh config");
 
-    println!("User: {} removed", user.green());
+    if is_dry_run() {
+        println!("Would remove user: {}", user.green());
+    } else {
+        println!("User: {} removed", user.green());
+    }
 }
 
-pub fn handle_user_switch(user: UserArgs) {
-    let user = user.user;
+pub fn handle_user_switch(user_args: SwitchArgs) {
+    if user_args.global {
+        let Some(config) = load_config() else {
+            return;
+        };
+        let Some(user) = resolve_user(&config, user_args.user, "Which user to switch to globally?")
+        else {
+            println!("see ya (¯꒳¯)ᐝ");
+            return;
+        };
+        switch_global(&config, &user);
+        return;
+    }
 
-    if !check_cwd_is_repo() {
-        eprintln!("Current directory is not a git repository.");
+    if user_args.user.as_deref() == Some("-") {
+        eprintln!("`-` only works together with --global.");
         return;
     }
 
-    let config = match read_config_file() {
-        Ok(config) => config,
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            println!(
-                "Config not found, add a new user via `{}`!",
-                "tilb add".blue()
-            );
+    if user_args.auto {
+        let Some(config) = load_config() else {
+            return;
+        };
+
+        if let Some(dir) = user_args.recursive {
+            let results: Vec<(PathBuf, RepoOutcome)> = find_repos(&dir, true)
+                .into_iter()
+                .map(|repo| {
+                    let outcome = auto_switch_repo(&config, &repo, is_dry_run());
+                    (repo, outcome)
+                })
+                .collect();
+            print_repo_report(&results, is_dry_run());
             return;
         }
-        Err(err) => {
-            eprintln!("Error reading config: {}", err);
+
+        if !check_cwd_is_repo() {
+            eprintln!("Current directory is not a git repository.");
             return;
         }
+
+        // a dry run still goes through the git layer here, which shows the changes
+        match auto_switch_repo(&config, Path::new("."), false) {
+            RepoOutcome::Changed(user, _) if is_dry_run() => {
+                println!("Would switch to user: {}", user.green())
+            }
+            RepoOutcome::Changed(user, _) => println!("Switched to user: {}", user.green()),
+            RepoOutcome::Unchanged(user) => println!("Already using user: {}", user.green()),
+            RepoOutcome::Skipped(reason) => println!("Nothing to do, {}", reason),
+            RepoOutcome::Failed(reason) => eprintln!("Error switching repo: {}", reason),
+        }
+        return;
+    }
+
+    if !check_cwd_is_repo() {
+        eprintln!("Current directory is not a git repository.");
+        return;
+    }
+
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(user) = resolve_user(&config, user_args.user, "Which user to switch to?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
     };
 
     let selected_user = if let Some(user_fetehed) = config.users.get(&user) {
@@ -266,88 +743,2843 @@ pub fn handle_user_switch(user: UserArgs) {
         return;
     };
 
-    let (repo_owner, repo_name) = match get_repo_name() {
+    let (repo_owner, repo_name) = match get_repo_name(Path::new(".")) {
         Some(origin_url) => parse_origin_url(&origin_url).unwrap_or_else(|| {
             eprintln!("Couldn't parse origin URL, falling back to cached owner");
             let repo_name = get_repo_name_from_user();
-            (selected_user.name.clone(), repo_name)
+            (selected_user.username.clone(), repo_name)
         }),
         None => {
             let repo_name = get_repo_name_from_user();
-            (selected_user.name.clone(), repo_name)
-        }
-    };
-
-    Command::new("git")
-        .arg("config")
-        .arg("--local")
-        .arg("user.name")
-        .arg(&selected_user.name)
-        .status()
-        .expect("Failed to set git user.name");
-
-    Command::new("git")
-        .arg("config")
-        .arg("--local")
-        .arg("user.email")
-        .arg(&selected_user.email)
-        .status()
-        .expect("Failed to set git user.email");
-
-    if selected_user.use_https {
-        let full_origin = format!(
-            "https://{}/{}/{}",
-            selected_user.git_host, repo_owner, repo_name
-        );
-
-        match set_git_remote(&full_origin) {
-            Ok(()) => {}
-            Err(err) => {
-                eprintln!("Error setting git remote: {}", err);
-                return;
-            }
+            (selected_user.username.clone(), repo_name)
         }
+    };
+
+    if let Err(err) = switch_repo(
+        Path::new("."),
+        selected_user,
+        Some((&repo_owner, &repo_name)),
+    ) {
+        eprintln!("Error switching repo: {}", err);
+        return;
+    }
+
+    if is_dry_run() {
+        println!("Would switch to user: {}", user.green());
     } else {
-        let full_origin = format!(
-            "git@{}-{}:{}/{}",
-            selected_user.git_host, selected_user.name, repo_owner, repo_name
-        );
+        println!("Switched to user: {}", user.green());
+    }
+}
 
-        match set_git_remote(&full_origin) {
-            Ok(()) => {}
-            Err(err) => {
-                eprintln!("Error setting git remote: {}", err);
-                return;
-            }
+struct ConfigChange {
+    key: String,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// The local config keys `switch_repo` would change, with their old and new values
+fn plan_repo_switch(repo: &Path, user: &User, origin: Option<(&str, &str)>) -> Vec<ConfigChange> {
+    let identity = get_identity_config(user);
+    let mut planned: Vec<(&str, Option<String>)> = identity
+        .iter()
+        .map(|(key, value)| (*key, Some(value.clone())))
+        .collect();
+    for key in OPTIONAL_KEYS {
+        if !identity.iter().any(|(k, _)| *k == key) {
+            planned.push((key, None));
         }
     }
+    let extra = get_extra_config(user);
+    let managed = get_managed_keys(repo);
+    for (key, value) in &extra {
+        planned.push((key, Some(value.clone())));
+    }
+    for key in &managed {
+        if !extra.iter().any(|(k, _)| k == key) {
+            planned.push((key, get_local_config(repo, &format!("tilb.{}", key))));
+        }
+    }
+    if let Some((repo_owner, repo_name)) = origin {
+        planned.push((
+            "remote.origin.url",
+            Some(get_remote_url(user, repo_owner, repo_name)),
+        ));
+    }
 
-    println!("Switched to user: {}", selected_user.name.green());
+    planned
+        .into_iter()
+        .filter_map(|(key, after)| {
+            let before = get_local_config(repo, key);
+            (before != after).then(|| ConfigChange {
+                key: key.to_string(),
+                before,
+                after,
+            })
+        })
+        .collect()
 }
 
-pub fn handle_user_list() {
-    match read_config_file() {
-        Ok(config) => {
-            println!("Users:");
-            if config.users.is_empty() {
+/// Applies a user's identity to a repo and points origin at their credentials,
+/// origin is left alone when the owner and name aren't known
+fn switch_repo(
+    repo: &Path,
+    user: &User,
+    origin: Option<(&str, &str)>,
+) -> Result<Vec<ConfigChange>, Box<dyn std::error::Error>> {
+    let changes = plan_repo_switch(repo, user, origin);
+
+    save_original_config(repo)?;
+    apply_user_config(repo, user)?;
+    if let Some((repo_owner, repo_name)) = origin {
+        set_git_remote(repo, &get_remote_url(user, repo_owner, repo_name))?;
+    }
+
+    Ok(changes)
+}
+
+enum RepoOutcome {
+    Changed(String, Vec<ConfigChange>),
+    Unchanged(String),
+    Skipped(String),
+    Failed(String),
+}
+
+fn get_repo_remote(config: &UsersConfig, repo: &Path) -> Result<Option<RemoteInfo>, String> {
+    match get_repo_name(repo) {
+        Some(url) => resolve_remote(config, &url)
+            .map(Some)
+            .ok_or_else(|| format!("couldn't parse origin {}", url)),
+        None => Ok(None),
+    }
+}
+
+fn switch_repo_outcome(
+    repo: &Path,
+    key: &str,
+    user: &User,
+    remote: Option<&RemoteInfo>,
+    dry_run: bool,
+) -> RepoOutcome {
+    let origin = remote.map(|remote| (remote.owner.as_str(), remote.repo.as_str()));
+
+    let changes = if dry_run {
+        Ok(plan_repo_switch(repo, user, origin))
+    } else {
+        switch_repo(repo, user, origin)
+    };
+
+    match changes {
+        Ok(changes) if changes.is_empty() => RepoOutcome::Unchanged(key.to_string()),
+        Ok(changes) => RepoOutcome::Changed(key.to_string(), changes),
+        Err(err) => RepoOutcome::Failed(err.to_string()),
+    }
+}
+
+fn auto_switch_repo(config: &UsersConfig, repo: &Path, dry_run: bool) -> RepoOutcome {
+    let remote = match get_repo_remote(config, repo) {
+        Ok(remote) => remote,
+        Err(reason) => return RepoOutcome::Failed(reason),
+    };
+
+    let repo_path = get_repo_root(repo);
+    let Some(rule) = find_rule(config, repo_path.as_deref(), remote.as_ref()) else {
+        return RepoOutcome::Skipped("no rule matches".to_string());
+    };
+    let Some(user) = config.users.get(&rule.user) else {
+        return RepoOutcome::Failed(format!("rule wants '{}', who isn't in config", rule.user));
+    };
+
+    switch_repo_outcome(repo, &rule.user, user, remote.as_ref(), dry_run)
+}
+
+fn print_repo_report(results: &[(PathBuf, RepoOutcome)], dry_run: bool) {
+    if results.is_empty() {
+        println!("No git repos found.");
+        return;
+    }
+
+    let width = results
+        .iter()
+        .map(|(repo, _)| repo.display().to_string().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let (mut changed, mut unchanged, mut skipped, mut failed) = (0, 0, 0, 0);
+    for (repo, outcome) in results {
+        let (status, detail) = match outcome {
+            RepoOutcome::Changed(user, _) => {
+                changed += 1;
+                (
+                    format!("{:<9}", if dry_run { "would set" } else { "changed" })
+                        .green()
+                        .to_string(),
+                    user.clone(),
+                )
+            }
+            RepoOutcome::Unchanged(user) => {
+                unchanged += 1;
+                (format!("{:<9}", "unchanged"), user.clone())
+            }
+            RepoOutcome::Skipped(reason) => {
+                skipped += 1;
+                (
+                    format!("{:<9}", "skipped").yellow().to_string(),
+                    reason.clone(),
+                )
+            }
+            RepoOutcome::Failed(reason) => {
+                failed += 1;
+                (
+                    format!("{:<9}", "failed").bright_red().to_string(),
+                    reason.clone(),
+                )
+            }
+        };
+        println!(
+            "{}  {:<width$}  {}",
+            status,
+            repo.display(),
+            detail,
+            width = width
+        );
+
+        if let RepoOutcome::Changed(_, changes) = outcome {
+            for change in changes {
                 println!(
-                    "(no users found, add a new user via `{}`)",
-                    "tilb add".blue()
+                    "    {}: {} -> {}",
+                    change.key,
+                    change.before.as_deref().unwrap_or("(unset)"),
+                    change.after.as_deref().unwrap_or("(unset)")
                 );
-                return;
-            }
-            for (_, user) in config.users {
-                println!("- {} <{}>", user.name.green(), user.email);
             }
         }
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-            println!(
-                "Config not found, add a new user via `{}`!",
-                "tilb add".blue()
-            );
-        }
-        Err(err) => {
-            eprintln!("Error reading config: {}", err);
-        }
+    }
+
+    println!(
+        "\n{} {}, {} unchanged, {} skipped, {} failed",
+        changed,
+        if dry_run { "would change" } else { "changed" },
+        unchanged,
+        skipped,
+        failed
+    );
+}
+
+fn set_or_unset_global(key: &str, value: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    match value {
+        Some(value) => set_global_config(key, value),
+        None => unset_global_config(key),
+    }
+}
+
+fn switch_global(config: &UsersConfig, user: &str) {
+    let mut state = match read_state_file() {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("Error reading state: {}", err);
+            return;
+        }
+    };
+
+    let found = GlobalIdentity {
+        name: get_global_config("user.name"),
+        email: get_global_config("user.email"),
+        committer_name: get_global_config("committer.name"),
+        committer_email: get_global_config("committer.email"),
+        profile: None,
+        ssh_host: None,
+    };
+
+    // trust what we recorded last time unless the global config was changed by hand since
+    let current = match state.global.current.take() {
+        Some(recorded)
+            if recorded.name == found.name
+                && recorded.email == found.email
+                && recorded.committer_name == found.committer_name
+                && recorded.committer_email == found.committer_email =>
+        {
+            recorded
+        }
+        _ => found,
+    };
+
+    let target = if user == "-" {
+        match state.global.previous.take() {
+            Some(previous) => previous,
+            None => {
+                eprintln!("No previous global user to go back to.");
+                return;
+            }
+        }
+    } else {
+        let Some(selected_user) = config.users.get(user) else {
+            eprintln!("User '{}' not found in config.", user);
+            return;
+        };
+        GlobalIdentity {
+            name: Some(selected_user.author_name().to_string()),
+            email: Some(selected_user.email.clone()),
+            committer_name: selected_user.committer_name.clone(),
+            committer_email: selected_user.committer_email.clone(),
+            profile: Some(user.to_string()),
+            ssh_host: (!selected_user.use_https).then(|| selected_user.git_host.clone()),
+        }
+    };
+
+    // the old default ssh block goes away unless the new one replaces it
+    if let Some(host) = &current.ssh_host
+        && target.ssh_host.as_ref() != Some(host)
+        && let Err(err) = remove_from_ssh_config(host)
+    {
+        eprintln!("Error updating ssh config: {}", err);
+        return;
+    }
+
+    if let (Some(host), Some(user)) = (
+        &target.ssh_host,
+        target
+            .profile
+            .as_ref()
+            .and_then(|profile| config.users.get(profile)),
+    ) {
+        let ssh_path = get_key_path(&user.username)
+            .expect("no home dir")
+            .display()
+            .to_string();
+        if let Err(err) = add_to_ssh_config(host, host, "git", &ssh_path, &user.ssh_options) {
+            eprintln!("Error updating ssh config: {}", err);
+            return;
+        }
+    }
+
+    if let Err(err) = set_or_unset_global("user.name", target.name.as_deref())
+        .and_then(|_| set_or_unset_global("user.email", target.email.as_deref()))
+        .and_then(|_| set_or_unset_global("committer.name", target.committer_name.as_deref()))
+        .and_then(|_| set_or_unset_global("committer.email", target.committer_email.as_deref()))
+    {
+        eprintln!("Error setting global git config: {}", err);
+        return;
+    }
+
+    let display_name = target
+        .profile
+        .clone()
+        .or_else(|| target.name.clone())
+        .unwrap_or_else(|| "(no user)".to_string());
+
+    state.global.previous = Some(current);
+    state.global.current = Some(target);
+    if let Err(err) = write_state_file(&state) {
+        eprintln!("Error writing state: {}", err);
+        return;
+    }
+
+    if is_dry_run() {
+        println!("Would switch global user to: {}", display_name.green());
+    } else {
+        println!("Switched global user to: {}", display_name.green());
+    }
+}
+
+pub fn handle_user_edit(user_args: UserArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(user) = resolve_user(&config, user_args.user, "Which user to edit?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+
+    let selected_user = if let Some(user_fetched) = config.users.get(&user) {
+        user_fetched
+    } else {
+        eprintln!("User '{}' not found in config.", user);
+        return;
+    };
+
+    let author_name = match Text::new("Name on commits (leave empty for the username):")
+        .with_initial_value(selected_user.author_name.as_deref().unwrap_or(""))
+        .prompt()
+    {
+        Ok(name) if name.trim().is_empty() => None,
+        Ok(name) => Some(name.trim().to_string()),
+        Err(_) => {
+            println!("see ya (¯꒳¯)ᐝ");
+            return;
+        }
+    };
+
+    let email = match Text::new("Email:")
+        .with_initial_value(&selected_user.email)
+        .prompt()
+    {
+        Ok(email) => email.trim().to_string(),
+        Err(_) => {
+            println!("see ya (¯꒳¯)ᐝ");
+            return;
+        }
+    };
+
+    if !is_reasonable_email(&email) {
+        eprintln!(
+            "Erm, '{}' doesn't look like a valid email address",
+            email.bright_red()
+        );
+        return;
+    }
+
+    let website = match Text::new("Git host:")
+        .with_initial_value(&selected_user.git_host)
+        .prompt()
+    {
+        Ok(website) => website,
+        Err(_) => {
+            println!("see ya (¯꒳¯)ᐝ");
+            return;
+        }
+    };
+
+    let Some(domain_name) = parse_domain_name(website.trim()) else {
+        eprintln!("Failed to parse domain name from URL: {}", website);
+        return;
+    };
+
+    let default_branch = match Text::new("Default branch for `tilb init` (leave empty for git's):")
+        .with_initial_value(selected_user.default_branch.as_deref().unwrap_or(""))
+        .prompt()
+    {
+        Ok(branch) if branch.trim().is_empty() => None,
+        Ok(branch) => Some(branch.trim().to_string()),
+        Err(_) => {
+            println!("see ya (¯꒳¯)ᐝ");
+            return;
+        }
+    };
+
+    let sign_commits = if selected_user.use_https {
+        false
+    } else {
+        match Confirm::new("Sign commits and tags with the ssh key?")
+            .with_default(selected_user.sign_commits)
+            .prompt()
+        {
+            Ok(sign) => sign,
+            Err(_) => {
+                println!("see ya (¯꒳¯)ᐝ");
+                return;
+            }
+        }
+    };
+
+    if !selected_user.use_https && domain_name != selected_user.git_host {
+        let ssh_path = get_key_path(&selected_user.username)
+            .expect("no home dir")
+            .display()
+            .to_string();
+
+        add_to_ssh_config(
+            &get_host_alias(&selected_user.username),
+            &domain_name,
+            "git",
+            &ssh_path,
+            &selected_user.ssh_options,
+        )
+        .expect("failed to update ssh config");
+    }
+
+    let edited_user = User {
+        username: selected_user.username.clone(),
+        author_name,
+        email: email.clone(),
+        committer_name: selected_user.committer_name.clone(),
+        committer_email: selected_user.committer_email.clone(),
+        git_host: domain_name,
+        use_https: selected_user.use_https,
+        sign_commits,
+        default_branch,
+        gitconfig: selected_user.gitconfig.clone(),
+        ssh_options: selected_user.ssh_options.clone(),
+        template: selected_user.template.clone(),
+    };
+
+    if let Err(err) = add_user(edited_user) {
+        eprintln!("Error updating config: {}", err);
+        return;
+    }
+
+    if is_dry_run() {
+        println!("Would update user: {} <{}>", user.green(), email.green());
+    } else {
+        println!("User: {} <{}> updated", user.green(), email.green());
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UserListing {
+    /// What commands take to pick the user, the key under [users]
+    profile: String,
+    username: String,
+    author_name: String,
+    email: String,
+    host: String,
+    transport: String,
+    ssh_alias: Option<String>,
+    key_path: Option<String>,
+    key_exists: bool,
+    key_fingerprint: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct UserListings {
+    users: Vec<UserListing>,
+}
+
+fn build_user_listing(profile: &str, user: &User) -> UserListing {
+    if user.use_https {
+        return UserListing {
+            profile: profile.to_string(),
+            username: user.username.clone(),
+            author_name: user.author_name().to_string(),
+            email: user.email.clone(),
+            host: user.git_host.clone(),
+            transport: "https".to_string(),
+            ssh_alias: None,
+            key_path: None,
+            key_exists: false,
+            key_fingerprint: None,
+        };
+    }
+
+    let key_path = get_key_path(&user.username);
+    let key_exists = key_path.as_ref().is_some_and(|p| p.exists());
+    let key_fingerprint = key_path
+        .as_ref()
+        .filter(|_| key_exists)
+        .and_then(|p| get_key_fingerprint(&p.with_extension("pub")));
+
+    UserListing {
+        profile: profile.to_string(),
+        username: user.username.clone(),
+        author_name: user.author_name().to_string(),
+        email: user.email.clone(),
+        host: user.git_host.clone(),
+        transport: "ssh".to_string(),
+        ssh_alias: Some(get_host_alias(&user.username)),
+        key_path: key_path.map(|p| p.display().to_string()),
+        key_exists,
+        key_fingerprint,
+    }
+}
+
+fn print_user_table(listings: &[UserListing]) {
+    let header = [
+        "USER",
+        "USERNAME",
+        "AUTHOR",
+        "EMAIL",
+        "HOST",
+        "TRANSPORT",
+        "SSH ALIAS",
+        "KEY",
+        "FINGERPRINT",
+    ];
+    let rows: Vec<[String; 9]> = listings
+        .iter()
+        .map(|l| {
+            [
+                l.profile.clone(),
+                l.username.clone(),
+                l.author_name.clone(),
+                l.email.clone(),
+                l.host.clone(),
+                l.transport.clone(),
+                l.ssh_alias.clone().unwrap_or_else(|| "-".to_string()),
+                match (&l.key_path, l.key_exists) {
+                    (None, _) => "-".to_string(),
+                    (Some(_), true) => "present".to_string(),
+                    (Some(_), false) => "missing".to_string(),
+                },
+                l.key_fingerprint.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(header.to_vec()));
+    for row in &rows {
+        println!("{}", format_row(row.iter().map(|c| c.as_str()).collect()));
+    }
+}
+
+pub fn handle_repo_clone(clone_args: CloneArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(selected_user) = config.users.get(&clone_args.user) else {
+        eprintln!("User '{}' not found in config.", clone_args.user);
+        return;
+    };
+
+    // accept a full url as well as owner/repo
+    let repo_spec = clone_args.repo.trim();
+    let parsed = if repo_spec.contains("://") || repo_spec.contains('@') {
+        parse_origin_url(repo_spec)
+    } else {
+        repo_spec
+            .trim_end_matches(".git")
+            .split_once('/')
+            .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+            .map(|(owner, repo)| (owner.to_string(), repo.to_string()))
+    };
+
+    let Some((repo_owner, repo_name)) = parsed else {
+        eprintln!(
+            "Couldn't parse '{}', expected something like owner/repo",
+            clone_args.repo.bright_red()
+        );
+        return;
+    };
+
+    let dir = clone_args.dir.unwrap_or_else(|| PathBuf::from(&repo_name));
+    let full_origin = get_remote_url(selected_user, &repo_owner, &repo_name);
+
+    println!(
+        "Cloning {}/{} as {}",
+        repo_owner,
+        repo_name,
+        selected_user.username.green()
+    );
+
+    if let Err(err) = clone_repo(&full_origin, &dir) {
+        eprintln!("Error cloning repo: {}", err);
+        return;
+    }
+
+    if let Err(err) = apply_user_config(&dir, selected_user) {
+        eprintln!("Error setting git config: {}", err);
+        return;
+    }
+
+    println!(
+        "Cloned into {} as user: {}",
+        dir.display(),
+        selected_user.username.green()
+    );
+}
+
+pub fn handle_repo_init(init_args: InitArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(selected_user) = config.users.get(&init_args.user) else {
+        eprintln!("User '{}' not found in config.", init_args.user);
+        return;
+    };
+
+    let cwd = match std::env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(err) => {
+            eprintln!("Error reading current directory: {}", err);
+            return;
+        }
+    };
+
+    let repo_owner = init_args
+        .owner
+        .unwrap_or_else(|| selected_user.username.clone());
+    let Some(repo_name) = init_args.name.or_else(|| {
+        cwd.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+    }) else {
+        eprintln!("Couldn't tell the repo name from the current directory, pass --name");
+        return;
+    };
+
+    if check_cwd_is_repo() {
+        eprintln!(
+            "Current directory is already a git repository, use `{}` instead.",
+            "tilb switch".blue()
+        );
+        return;
+    }
+
+    if let Err(err) = init_repo(&cwd, selected_user.default_branch.as_deref()) {
+        eprintln!("Error creating repo: {}", err);
+        return;
+    }
+
+    if let Err(err) = apply_user_config(&cwd, selected_user) {
+        eprintln!("Error setting git config: {}", err);
+        return;
+    }
+
+    let full_origin = get_remote_url(selected_user, &repo_owner, &repo_name);
+    if let Err(err) = set_git_remote(&cwd, &full_origin) {
+        eprintln!("Error setting git remote: {}", err);
+        return;
+    }
+
+    println!(
+        "Created {}/{} as user: {}",
+        repo_owner,
+        repo_name,
+        selected_user.username.green()
+    );
+}
+
+pub fn handle_exec(exec_args: ExecArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(selected_user) = config.users.get(&exec_args.user) else {
+        eprintln!("User '{}' not found in config.", exec_args.user);
+        return;
+    };
+
+    let Some((program, program_args)) = exec_args.command.split_first() else {
+        eprintln!("Nothing to run, pass the command after `--`.");
+        return;
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(program_args)
+        .envs(get_identity_env(selected_user));
+
+    // append to any GIT_CONFIG_* overrides we were started with rather than clobbering them
+    let offset: usize = std::env::var("GIT_CONFIG_COUNT")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    let entries: Vec<(String, String)> = get_identity_config(selected_user)
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .chain(get_extra_config(selected_user))
+        .collect();
+    for (i, (key, value)) in entries.iter().enumerate() {
+        command
+            .env(format!("GIT_CONFIG_KEY_{}", offset + i), key)
+            .env(format!("GIT_CONFIG_VALUE_{}", offset + i), value);
+    }
+    command.env("GIT_CONFIG_COUNT", (offset + entries.len()).to_string());
+
+    // nothing runs in a dry run, it only shows what the command would get
+    if is_dry_run() {
+        println!("{}", "environment".bold());
+        for (name, value) in command.get_envs() {
+            println!(
+                "  {}={}",
+                name.to_string_lossy(),
+                value.unwrap_or_default().to_string_lossy()
+            );
+        }
+        changes::record_command(exec_args.command.join(" "));
+        return;
+    }
+
+    info!(
+        "Running {:?} as {}",
+        exec_args.command, selected_user.username
+    );
+
+    match command.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(err) => {
+            eprintln!("Error running {}: {}", program, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn guess_shell() -> Shell {
+    if cfg!(windows) {
+        return Shell::Powershell;
+    }
+
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    if shell.ends_with("fish") {
+        Shell::Fish
+    } else if shell.ends_with("zsh") {
+        Shell::Zsh
+    } else {
+        Shell::Bash
+    }
+}
+
+fn format_export(shell: Shell, name: &str, value: &str) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            format!("export {}='{}'", name, value.replace('\'', "'\\''"))
+        }
+        Shell::Fish => format!(
+            "set -gx {} '{}'",
+            name,
+            value.replace('\\', "\\\\").replace('\'', "\\'")
+        ),
+        Shell::Powershell => format!("$env:{} = '{}'", name, value.replace('\'', "''")),
+    }
+}
+
+fn format_unset(shell: Shell, name: &str) -> String {
+    match shell {
+        Shell::Bash | Shell::Zsh => format!("unset {}", name),
+        Shell::Fish => format!("set -e {}", name),
+        Shell::Powershell => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name),
+    }
+}
+
+pub fn handle_env(env_args: EnvArgs) {
+    let shell = env_args.shell.unwrap_or_else(guess_shell);
+
+    if env_args.unset {
+        for name in IDENTITY_ENV_VARS {
+            println!("{}", format_unset(shell, name));
+        }
+        return;
+    }
+
+    // failing loudly, an eval of nothing would leave the old identity in place
+    let Some(config) = load_config() else {
+        std::process::exit(1);
+    };
+
+    let user = env_args.user.unwrap_or_default();
+    let Some(selected_user) = config.users.get(&user) else {
+        eprintln!("User '{}' not found in config.", user);
+        std::process::exit(1);
+    };
+
+    let vars = get_identity_env(selected_user);
+    // clear anything this user doesn't set, e.g. GIT_SSH_COMMAND for https users
+    for name in IDENTITY_ENV_VARS {
+        if !vars.iter().any(|(n, _)| *n == name) {
+            println!("{}", format_unset(shell, name));
+        }
+    }
+    for (name, value) in vars {
+        println!("{}", format_export(shell, name, &value));
+    }
+}
+
+pub fn handle_apply(apply_args: ApplyArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let Some(selected_user) = config.users.get(&apply_args.user) else {
+        eprintln!("User '{}' not found in config.", apply_args.user);
+        return;
+    };
+
+    let results: Vec<(PathBuf, RepoOutcome)> = find_repos(&apply_args.dir, apply_args.recursive)
+        .into_iter()
+        .map(|repo| {
+            // a broken repo is reported, never stops the run
+            let outcome = match get_repo_remote(&config, &repo) {
+                Ok(remote) => switch_repo_outcome(
+                    &repo,
+                    &apply_args.user,
+                    selected_user,
+                    remote.as_ref(),
+                    is_dry_run(),
+                ),
+                Err(reason) => RepoOutcome::Failed(reason),
+            };
+            (repo, outcome)
+        })
+        .collect();
+
+    print_repo_report(&results, is_dry_run());
+}
+
+pub fn handle_user_list(list_args: ListArgs) {
+    let format = list_args.format;
+
+    let config = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            return;
+        }
+    };
+
+    // scripts get an empty list rather than a message
+    if config.users.is_empty() && (format == ListFormat::Plain || format == ListFormat::Table) {
+        println!("No users yet, add a new user via `{}`!", "tilb add".blue());
+        return;
+    }
+
+    let mut users: Vec<(&String, &User)> = config.users.iter().collect();
+    users.sort_by(|a, b| a.0.cmp(b.0));
+    let listings = || {
+        users
+            .iter()
+            .map(|(profile, user)| build_user_listing(profile, user))
+            .collect::<Vec<UserListing>>()
+    };
+
+    match format {
+        ListFormat::Plain => {
+            println!("Users:");
+            if users.is_empty() {
+                println!(
+                    "(no users found, add a new user via `{}`)",
+                    "tilb add".blue()
+                );
+                return;
+            }
+            for (profile, user) in &users {
+                // the username only when it isn't the name to pass to commands
+                let account = if **profile == user.username {
+                    String::new()
+                } else {
+                    format!(", username {}", user.username)
+                };
+                if user.author_name() == user.username {
+                    println!("- {}{} <{}>", profile.green(), account, user.email);
+                } else {
+                    println!(
+                        "- {}{} ({} <{}>)",
+                        profile.green(),
+                        account,
+                        user.author_name(),
+                        user.email
+                    );
+                }
+            }
+        }
+        ListFormat::Table => {
+            print_user_table(&listings());
+        }
+        ListFormat::Json => match serde_json::to_string_pretty(&listings()) {
+            Ok(json) => println!("{}", json),
+            Err(err) => eprintln!("Error serializing users: {}", err),
+        },
+        ListFormat::Toml => {
+            let listings = UserListings { users: listings() };
+            match toml::to_string_pretty(&listings) {
+                Ok(toml_str) => print!("{}", toml_str),
+                Err(err) => eprintln!("Error serializing users: {}", err),
+            }
+        }
+    }
+}
+
+pub fn handle_completions(completions_args: CompletionsArgs) {
+    let shell: &dyn EnvCompleter = match completions_args.shell {
+        Shell::Bash => &Bash,
+        Shell::Zsh => &Zsh,
+        Shell::Fish => &Fish,
+        Shell::Powershell => &Powershell,
+    };
+
+    // the script calls back into tilb, so profile names are always read fresh from config.toml
+    let mut stdout = std::io::stdout();
+    if let Err(err) = shell.write_registration("COMPLETE", "tilb", "tilb", "tilb", &mut stdout) {
+        eprintln!("Error writing completion script: {}", err);
+    }
+}
+
+pub fn handle_man(man_args: ManArgs) {
+    if let Some(out_dir) = man_args.out_dir {
+        match write_pages(TilbArgs::command(), &out_dir) {
+            Ok(pages) => {
+                for page in pages {
+                    println!("Wrote {}", out_dir.join(page).display());
+                }
+            }
+            Err(err) => eprintln!("Error writing man pages: {}", err),
+        }
+        return;
+    }
+
+    let mut stdout = std::io::stdout();
+    if let Err(err) = write_page(TilbArgs::command(), &man_args.page, &mut stdout) {
+        eprintln!("Error writing man page: {}", err);
+    }
+}
+
+pub fn handle_guard(guard_args: GuardArgs) {
+    match guard_args.action {
+        GuardActions::Install(install_args) => guard_install(install_args),
+        GuardActions::Uninstall(uninstall_args) => guard_uninstall(uninstall_args),
+        GuardActions::Status => guard_status(),
+        GuardActions::Check(check_args) => guard_check(check_args),
+    }
+}
+
+fn guard_install(install_args: GuardInstallArgs) {
+    if install_args.global {
+        let Some(dir) = get_global_hooks_dir() else {
+            eprintln!("Couldn't find your home directory.");
+            return;
+        };
+        let dir_str = dir.display().to_string();
+
+        if let Some(existing) = get_global_config("core.hooksPath")
+            && existing != dir_str
+            && !install_args.force
+        {
+            eprintln!(
+                "core.hooksPath is already set to {}, use --force to replace it",
+                existing.bright_red()
+            );
+            return;
+        }
+
+        if let Err(err) = install_hooks(&dir, true, install_args.force) {
+            eprintln!("Error installing hooks: {}", err);
+            return;
+        }
+        if let Err(err) = set_global_config("core.hooksPath", &dir_str) {
+            eprintln!("Error setting core.hooksPath: {}", err);
+            return;
+        }
+
+        println!(
+            "Guard hooks installed for every repo in {}",
+            dir_str.green()
+        );
+        return;
+    }
+
+    if !check_cwd_is_repo() {
+        eprintln!("Current directory is not a git repository.");
+        return;
+    }
+
+    let Some(dir) = get_hooks_dir() else {
+        eprintln!("Couldn't find the hooks directory of this repo.");
+        return;
+    };
+
+    match install_hooks(&dir, false, install_args.force) {
+        Ok(paths) => {
+            for path in paths {
+                println!("Installed {}", path.display().to_string().green());
+            }
+        }
+        Err(err) => eprintln!("Error installing hooks: {}", err),
+    }
+}
+
+fn guard_uninstall(uninstall_args: GuardUninstallArgs) {
+    let dir = if uninstall_args.global {
+        let Some(dir) = get_global_hooks_dir() else {
+            eprintln!("Couldn't find your home directory.");
+            return;
+        };
+
+        if get_global_config("core.hooksPath").as_deref() == Some(&dir.display().to_string())
+            && let Err(err) = unset_global_config("core.hooksPath")
+        {
+            eprintln!("Error unsetting core.hooksPath: {}", err);
+            return;
+        }
+        dir
+    } else {
+        if !check_cwd_is_repo() {
+            eprintln!("Current directory is not a git repository.");
+            return;
+        }
+        let Some(dir) = get_hooks_dir() else {
+            eprintln!("Couldn't find the hooks directory of this repo.");
+            return;
+        };
+        dir
+    };
+
+    match uninstall_hooks(&dir) {
+        Ok(paths) if paths.is_empty() => println!("No guard hooks found in {}", dir.display()),
+        Ok(paths) => {
+            for path in paths {
+                println!("Removed {}", path.display());
+            }
+        }
+        Err(err) => eprintln!("Error removing hooks: {}", err),
+    }
+}
+
+fn find_repo_rule<'a>(config: &'a UsersConfig, remote_url: Option<&str>) -> Option<&'a Rule> {
+    let repo_path = get_repo_root(Path::new("."));
+    let remote = remote_url.and_then(|url| resolve_remote(config, url));
+    find_rule(config, repo_path.as_deref(), remote.as_ref())
+}
+
+fn describe_rule(rule: &Rule) -> String {
+    match (&rule.remote, &rule.path) {
+        (Some(remote), Some(path)) => format!("remote {} under {}", remote, path),
+        (Some(remote), None) => format!("remote {}", remote),
+        (None, Some(path)) => format!("path {}", path),
+        (None, None) => "(empty rule)".to_string(),
+    }
+}
+
+fn check_pre_commit(config: &UsersConfig) -> Vec<String> {
+    let Some(rule) = find_repo_rule(config, get_repo_name(Path::new(".")).as_deref()) else {
+        return Vec::new();
+    };
+    let Some(expected) = config.users.get(&rule.user) else {
+        return vec![format!(
+            "the rule for {} wants user '{}', who isn't in config.toml",
+            describe_rule(rule),
+            rule.user
+        )];
+    };
+
+    match get_author_email() {
+        Some(email) if email.eq_ignore_ascii_case(&expected.email) => Vec::new(),
+        Some(email) => vec![format!(
+            "committing as <{}>, but this repo must use {} <{}>",
+            email, rule.user, expected.email
+        )],
+        None => vec!["couldn't tell who is committing, is user.email set?".to_string()],
+    }
+}
+
+fn check_pre_push(config: &UsersConfig, args: &[String]) -> Vec<String> {
+    let remote_name = args.first().map(|s| s.as_str()).unwrap_or("origin");
+    let remote_url = args
+        .get(1)
+        .cloned()
+        .or_else(|| get_repo_name(Path::new(".")));
+
+    let Some(rule) = find_repo_rule(config, remote_url.as_deref()) else {
+        return Vec::new();
+    };
+    let Some(expected) = config.users.get(&rule.user) else {
+        return vec![format!(
+            "the rule for {} wants user '{}', who isn't in config.toml",
+            describe_rule(rule),
+            rule.user
+        )];
+    };
+
+    let mut problems = Vec::new();
+
+    if let Some(remote) = remote_url
+        .as_deref()
+        .and_then(|url| resolve_remote(config, url))
+        && let Some(remote_user) = remote.user
+        && remote_user != rule.user
+    {
+        problems.push(format!(
+            "pushing with {}'s credentials, but this repo must use {}",
+            remote_user, rule.user
+        ));
+    }
+
+    // git feeds "<local ref> <local sha> <remote ref> <remote sha>" per ref being pushed
+    let mut emails: Vec<String> = Vec::new();
+    for line in std::io::stdin().lock().lines().map_while(|l| l.ok()) {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let [_, local_sha, _, remote_sha] = parts[..] else {
+            continue;
+        };
+        if local_sha.chars().all(|c| c == '0') {
+            continue; // deleting a branch
+        }
+
+        let remotes = format!("--remotes={}", remote_name);
+        let range = format!("{}..{}", remote_sha, local_sha);
+        let log_args: Vec<&str> = if remote_sha.chars().all(|c| c == '0') {
+            vec![local_sha, "--not", &remotes]
+        } else {
+            vec![&range]
+        };
+
+        // committer rather than author, pushing someone else's commits is fine
+        match get_commit_emails(&log_args) {
+            Ok(found) => emails.extend(found),
+            Err(err) => problems.push(format!("couldn't list the commits being pushed: {}", err)),
+        }
+    }
+
+    emails.sort();
+    emails.dedup();
+    for email in emails {
+        if !email.eq_ignore_ascii_case(&expected.email) {
+            problems.push(format!(
+                "a commit being pushed was made as <{}>, but this repo must use {} <{}>",
+                email, rule.user, expected.email
+            ));
+        }
+    }
+
+    problems
+}
+
+fn guard_check(check_args: GuardCheckArgs) {
+    let config = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("tilb guard: error reading config: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let problems = match check_args.hook.as_str() {
+        "pre-commit" => check_pre_commit(&config),
+        "pre-push" => check_pre_push(&config, &check_args.args),
+        _ => Vec::new(),
+    };
+
+    if problems.is_empty() {
+        return;
+    }
+
+    for problem in &problems {
+        eprintln!("tilb guard: {}", problem.bright_red());
+    }
+    eprintln!(
+        "Fix it with `{}` (and amend or rebase the commits), or skip the check once with --no-verify.",
+        "tilb switch".blue()
+    );
+    std::process::exit(1);
+}
+
+fn guard_status() {
+    if !check_cwd_is_repo() {
+        eprintln!("Current directory is not a git repository.");
+        return;
+    }
+
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let repo_path = get_repo_root(Path::new("."));
+    let remote_url = get_repo_name(Path::new("."));
+    let remote = remote_url
+        .as_deref()
+        .and_then(|url| resolve_remote(&config, url));
+
+    if let Some(repo_path) = &repo_path {
+        println!("Repo:   {}", repo_path.display());
+    }
+    match &remote {
+        Some(remote) => println!(
+            "Remote: {}/{}/{}{}",
+            remote.host,
+            remote.owner,
+            remote.repo,
+            remote
+                .user
+                .as_ref()
+                .map(|u| format!(" (as {})", u))
+                .unwrap_or_default()
+        ),
+        None => println!("Remote: (none)"),
+    }
+
+    match find_rule(&config, repo_path.as_deref(), remote.as_ref()) {
+        Some(rule) => {
+            println!("Rule:   {} -> {}", describe_rule(rule), rule.user.green());
+            let expected_email = config.users.get(&rule.user).map(|u| u.email.as_str());
+            let email = get_author_email();
+            match (email.as_deref(), expected_email) {
+                (_, None) => println!("Author: '{}' isn't in config.toml", rule.user.bright_red()),
+                (Some(email), Some(expected)) if email.eq_ignore_ascii_case(expected) => {
+                    println!("Author: <{}> {}", email, "ok".green())
+                }
+                (email, Some(expected)) => println!(
+                    "Author: <{}> {}, expected <{}>",
+                    email.unwrap_or("unset"),
+                    "mismatch".bright_red(),
+                    expected
+                ),
+            }
+        }
+        None => println!("Rule:   (no rule matches, anything goes)"),
+    }
+
+    let local = get_hooks_dir().is_some_and(|dir| {
+        dir.join("pre-commit").exists() && is_tilb_hook(&dir.join("pre-commit"))
+    });
+    let global = get_global_hooks_dir().is_some_and(|dir| {
+        get_global_config("core.hooksPath").as_deref() == Some(&dir.display().to_string())
+    });
+    let hooks = match (local, global) {
+        (_, true) => "installed globally".green().to_string(),
+        (true, false) => "installed in this repo".green().to_string(),
+        (false, false) => format!("not installed, see `{}`", "tilb guard install".blue()),
+    };
+    println!("Hooks:  {}", hooks);
+}
+
+fn set_or_unset_local(
+    repo: &Path,
+    key: &str,
+    value: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if MULTI_VALUED_MARKERS.contains(&key) {
+        let values: Vec<String> = value
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        return set_local_config_all(repo, key, &values);
+    }
+    match value {
+        Some(value) => set_local_config(repo, key, value),
+        None => unset_local_config(repo, key),
+    }
+}
+
+fn get_scoped_config(scope: &str, key: &str) -> Option<String> {
+    if scope == "global" {
+        get_global_config(key)
+    } else if MULTI_VALUED_MARKERS.contains(&key) {
+        let values = get_local_config_all(Path::new(scope), key);
+        (!values.is_empty()).then(|| values.join("\n"))
+    } else {
+        get_local_config(Path::new(scope), key)
+    }
+}
+
+/// What changed again after `operation`, which undoing it would throw away
+fn find_drift(operation: &Operation) -> Vec<String> {
+    let mut drifted = Vec::new();
+    for file in &operation.files {
+        let current = std::fs::read_to_string(&file.path).ok();
+        if current != file.after {
+            drifted.push(file.path.display().to_string());
+        }
+    }
+    // only the last value a run left behind for each key counts
+    let mut seen = HashSet::new();
+    for change in operation.git.iter().rev() {
+        if seen.insert((&change.scope, &change.key))
+            && get_scoped_config(&change.scope, &change.key) != change.after
+        {
+            drifted.push(format!("{} in {}", change.key, change.scope));
+        }
+    }
+    drifted
+}
+
+pub fn handle_undo(undo_args: UndoArgs) {
+    let mut journal = match read_journal() {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("Error reading journal: {}", err);
+            return;
+        }
+    };
+
+    let Some(operation) = journal.operations.last().cloned() else {
+        println!("Nothing to undo.");
+        return;
+    };
+
+    let drifted = find_drift(&operation);
+    if !drifted.is_empty() && !undo_args.force {
+        eprintln!(
+            "These changed again since `tilb {}`, use --force to revert anyway:",
+            operation.command
+        );
+        for what in drifted {
+            eprintln!("    {}", what.bright_red());
+        }
+        return;
+    }
+
+    for file in operation.files.iter().rev() {
+        let result = match &file.before {
+            Some(content) => changes::write(&file.path, content),
+            None if file.path.exists() => changes::remove_file(&file.path),
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("Error restoring {}: {}", file.path.display(), err);
+            return;
+        }
+    }
+
+    // newest first, so a key changed twice ends up with its oldest value
+    for change in operation.git.iter().rev() {
+        let result = if change.scope == "global" {
+            set_or_unset_global(&change.key, change.before.as_deref())
+        } else {
+            set_or_unset_local(
+                Path::new(&change.scope),
+                &change.key,
+                change.before.as_deref(),
+            )
+        };
+        if let Err(err) = result {
+            eprintln!(
+                "Error restoring {} in {}: {}",
+                change.key, change.scope, err
+            );
+            return;
+        }
+    }
+
+    for command in &operation.commands {
+        println!("Not undone, revert by hand if needed: {}", command.yellow());
+    }
+
+    if is_dry_run() {
+        return;
+    }
+
+    journal.operations.pop();
+    if let Err(err) = write_journal(&journal) {
+        eprintln!("Error writing journal: {}", err);
+        return;
+    }
+
+    println!("Undid `tilb {}`", operation.command.green());
+}
+
+fn format_age(time: u64) -> String {
+    let secs = now().saturating_sub(time);
+    let (amount, unit) = match secs {
+        0..60 => return "just now".to_string(),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        amount,
+        unit,
+        if amount == 1 { "" } else { "s" }
+    )
+}
+
+pub fn handle_history() {
+    let journal = match read_journal() {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("Error reading journal: {}", err);
+            return;
+        }
+    };
+
+    if journal.operations.is_empty() {
+        println!("No changes recorded yet.");
+        return;
+    }
+
+    for (i, operation) in journal.operations.iter().enumerate().rev() {
+        println!(
+            "{}  {}  tilb {}",
+            format!("#{}", i + 1).bold(),
+            format_age(operation.time),
+            operation.command.green()
+        );
+        for file in &operation.files {
+            let what = match (&file.before, &file.after) {
+                (None, _) => "created",
+                (_, None) => "removed",
+                _ => "changed",
+            };
+            println!("    {} {}", what, file.path.display());
+        }
+        for change in &operation.git {
+            println!(
+                "    {} {}: {} -> {}",
+                change.scope,
+                change.key,
+                changes::show_git_value(change.before.as_deref()),
+                changes::show_git_value(change.after.as_deref())
+            );
+        }
+        for command in &operation.commands {
+            println!("    ran {}", command);
+        }
+    }
+    println!();
+    println!(
+        "`{}` reverts #{}",
+        "tilb undo".blue(),
+        journal.operations.len()
+    );
+}
+
+pub fn handle_reset() {
+    if !check_cwd_is_repo() {
+        eprintln!("Current directory is not a git repository.");
+        return;
+    }
+
+    let repo = Path::new(".");
+    if get_local_config(repo, SAVED_MARKER).is_none() {
+        println!("Nothing to reset, tilb hasn't switched this repo.");
+        return;
+    }
+
+    let managed = get_managed_keys(repo);
+    for key in SAVED_KEYS
+        .iter()
+        .copied()
+        .chain(managed.iter().map(String::as_str))
+    {
+        let original = get_local_config(repo, &format!("tilb.{}", key));
+        if let Err(err) = set_or_unset_local(repo, key, original.as_deref()) {
+            eprintln!("Error restoring {}: {}", key, err);
+            return;
+        }
+    }
+
+    // a repo that had no origin gets none back, not a remote without a url
+    if get_local_config(repo, "remote.origin.url").is_none()
+        && let Err(err) = unset_local_config(repo, "remote.origin.fetch")
+    {
+        eprintln!("Error removing origin: {}", err);
+        return;
+    }
+
+    let mut keys = get_local_config_keys(repo, "^tilb\\.");
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        if let Err(err) = set_or_unset_local(repo, &key, None) {
+            eprintln!("Error clearing {}: {}", key, err);
+            return;
+        }
+    }
+
+    if is_dry_run() {
+        println!("Would restore the config this repo had before tilb switched it");
+    } else {
+        println!("Restored the config this repo had before tilb switched it");
+    }
+}
+
+/// Moves ~/.tilb to the XDG config dir once, keeping global hooks and the journal pointing at it
+pub fn migrate_home() {
+    let (from, to) = match migrate_legacy_home() {
+        Ok(Some(moved)) => moved,
+        Ok(None) => {
+            if let Some((legacy, xdg)) = get_ignored_legacy_home() {
+                eprintln!(
+                    "Warning: {} is ignored since {} exists, merge or remove it",
+                    legacy.display(),
+                    xdg.display()
+                );
+            }
+            return;
+        }
+        Err(err) => {
+            eprintln!(
+                "Couldn't move ~/.tilb to the XDG config dir, still using it: {}",
+                err
+            );
+            return;
+        }
+    };
+    eprintln!("Moved {} to {}", from.display(), to.display());
+
+    let old_hooks = from.join("hooks").display().to_string();
+    if get_global_config("core.hooksPath").as_deref() == Some(&old_hooks)
+        && let Err(err) =
+            set_global_config("core.hooksPath", &to.join("hooks").display().to_string())
+    {
+        eprintln!("Error updating core.hooksPath: {}", err);
+    }
+
+    // paths recorded before the move still point into the old home
+    match read_journal() {
+        Ok(journal) if journal.operations.is_empty() => {}
+        Ok(mut journal) => {
+            for operation in &mut journal.operations {
+                for file in &mut operation.files {
+                    file.path = rebase_path(&file.path, &from, &to);
+                }
+            }
+            if let Err(err) = write_journal(&journal) {
+                eprintln!("Error writing journal: {}", err);
+            }
+        }
+        Err(err) => eprintln!("Error reading journal: {}", err),
+    }
+
+    // the move isn't something `tilb undo` should put back
+    changes::discard();
+}
+
+struct Finding {
+    path: PathBuf,
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl Finding {
+    fn at(path: &Path, content: &str, offset: usize, message: String) -> Finding {
+        let (line, column) = locate(content, offset);
+        Finding {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.message
+        )
+    }
+}
+
+/// `Host` lines in ~/.ssh/config naming one of `aliases`, as (alias, byte offset)
+fn find_ssh_hosts(content: &str, aliases: &HashSet<String>) -> Vec<(String, usize)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if let Some((keyword, names)) = trimmed.split_once(char::is_whitespace)
+            && keyword.eq_ignore_ascii_case("host")
+        {
+            for name in names.split_whitespace() {
+                let name = name.to_lowercase();
+                if aliases.contains(&name) {
+                    found.push((name, offset + line.len() - trimmed.len()));
+                }
+            }
+        }
+        offset += line.len();
+    }
+    found
+}
+
+/// Whether a gitconfig value has an array anywhere in it, git keys take one value
+fn has_array(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Array(_) => true,
+        toml::Value::Table(table) => table.values().any(has_array),
+        _ => false,
+    }
+}
+
+/// Every problem in config.toml `content`, or the parse error when it doesn't parse
+fn check_config(path: &Path, content: &str) -> Result<Vec<Finding>, String> {
+    let config = parse_config(path, content).map_err(|e| e.to_string())?;
+    // parse_config would have failed already if this did, bar a migrated v0 file
+    let spanned: SpannedConfig = toml::from_str(content).map_err(|e| e.to_string())?;
+
+    let mut findings = Vec::new();
+    let mut aliases: HashMap<String, Vec<&str>> = HashMap::new();
+
+    for (key, user) in &spanned.users {
+        let name = user.username.get_ref();
+
+        if !is_reasonable_email(user.email.get_ref()) {
+            findings.push(Finding::at(
+                path,
+                content,
+                user.email.span().start,
+                format!(
+                    "\"{}\" doesn't look like an email address",
+                    user.email.get_ref()
+                ),
+            ));
+        }
+
+        if !is_git_hosting_site(user.git_host.get_ref()) {
+            findings.push(Finding::at(
+                path,
+                content,
+                user.git_host.span().start,
+                format!("unknown git host \"{}\"", user.git_host.get_ref()),
+            ));
+        }
+
+        for (top_key, value) in &user.gitconfig {
+            let mut table = Table::new();
+            table.insert(top_key.get_ref().clone(), value.clone());
+            let flattened = flatten_gitconfig(&table);
+
+            if has_array(value) {
+                findings.push(Finding::at(
+                    path,
+                    content,
+                    top_key.span().start,
+                    "gitconfig values can't be arrays, give each key a single value".to_string(),
+                ));
+            }
+            for (git_key, _) in flattened.iter().filter(|(k, _)| is_reserved_key(k)) {
+                findings.push(Finding::at(
+                    path,
+                    content,
+                    top_key.span().start,
+                    format!(
+                        "{} is set by tilb itself, it's ignored in gitconfig",
+                        git_key
+                    ),
+                ));
+            }
+        }
+
+        for (option, value) in &user.ssh_options {
+            if let Err(problem) = check_ssh_option(option.get_ref(), value.get_ref()) {
+                findings.push(Finding::at(path, content, option.span().start, problem));
+            }
+        }
+
+        if !user.use_https
+            && let Some(key_path) = get_key_path(name)
+            && !key_path.exists()
+        {
+            findings.push(Finding::at(
+                path,
+                content,
+                key.span().start,
+                format!("ssh key {} is missing", key_path.display()),
+            ));
+        }
+
+        // ssh matches host names without regard to case
+        aliases
+            .entry(get_host_alias(name).to_lowercase())
+            .or_default()
+            .push(key.get_ref());
+    }
+
+    for (key, user) in &spanned.users {
+        let alias = get_host_alias(user.username.get_ref()).to_lowercase();
+        let others: Vec<&str> = aliases[&alias]
+            .iter()
+            .copied()
+            .filter(|other| *other != key.get_ref())
+            .collect();
+        if !others.is_empty() {
+            findings.push(Finding::at(
+                path,
+                content,
+                user.username.span().start,
+                format!("ssh alias {} is also used by {}", alias, others.join(", ")),
+            ));
+        }
+    }
+
+    for rule in &spanned.rules {
+        if !config.users.contains_key(rule.user.get_ref()) {
+            findings.push(Finding::at(
+                path,
+                content,
+                rule.user.span().start,
+                format!("rule points at unknown user \"{}\"", rule.user.get_ref()),
+            ));
+        }
+    }
+
+    // a block defined twice in ~/.ssh/config only has its first copy used
+    if let Some(ssh_path) = get_ssh_config_path()
+        && let Ok(ssh_content) = changes::read_to_string(&ssh_path)
+    {
+        let wanted: HashSet<String> = aliases.keys().cloned().collect();
+        let mut seen = HashSet::new();
+        for (alias, offset) in find_ssh_hosts(&ssh_content, &wanted) {
+            if !seen.insert(alias.clone()) {
+                findings.push(Finding::at(
+                    &ssh_path,
+                    &ssh_content,
+                    offset,
+                    format!(
+                        "Host {} is defined more than once, ssh only uses the first",
+                        alias
+                    ),
+                ));
+            }
+        }
+    }
+
+    findings.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    Ok(findings)
+}
+
+fn print_findings(findings: &[Finding]) {
+    for finding in findings {
+        println!("{}", finding.to_string().yellow());
+    }
+}
+
+fn config_check() {
+    let Some(path) = get_config_path() else {
+        eprintln!("Couldn't find your home directory.");
+        return;
+    };
+    let content = match changes::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            println!("No config at {} yet, nothing to check.", path.display());
+            return;
+        }
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    match check_config(&path, &content) {
+        Ok(findings) if findings.is_empty() => println!("{} looks good", path.display()),
+        Ok(findings) => {
+            print_findings(&findings);
+            println!(
+                "\n{} problem{} found",
+                findings.len(),
+                if findings.len() == 1 { "" } else { "s" }
+            );
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("{}", err.bright_red());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_editor(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // through the shell, so editors with flags like "code --wait" work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("tilb")
+        .arg(path)
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} exited with {}", editor, status).into())
+    }
+}
+
+fn config_edit() {
+    let Some(path) = get_config_path() else {
+        eprintln!("Couldn't find your home directory.");
+        return;
+    };
+    let original = match changes::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            toml::to_string_pretty(&UsersConfig::default()).unwrap_or_default()
+        }
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            return;
+        }
+    };
+
+    // edits land in a private scratch copy, config.toml only changes once they check out
+    let scratch_dir = match ScratchDir::new("tilb-config") {
+        Ok(dir) => dir,
+        Err(err) => {
+            eprintln!("Error creating a scratch directory: {}", err);
+            return;
+        }
+    };
+    let scratch = match scratch_dir.create_file("config.toml", &original) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("Error creating the scratch config: {}", err);
+            return;
+        }
+    };
+
+    let edited = loop {
+        if let Err(err) = run_editor(&scratch) {
+            eprintln!("Error running editor: {}", err);
+            break None;
+        }
+        let content = match std::fs::read_to_string(&scratch) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("Error reading {}: {}", scratch.display(), err);
+                break None;
+            }
+        };
+
+        let (problems, can_save) = match check_config(&path, &content) {
+            Ok(findings) if findings.is_empty() => break Some(content),
+            Ok(findings) => {
+                print_findings(&findings);
+                (findings.len(), true)
+            }
+            Err(err) => {
+                eprintln!("{}", err.bright_red());
+                (1, false)
+            }
+        };
+
+        let prompt = if can_save {
+            format!(
+                "{} problem(s) found, edit again? (no saves it as is)",
+                problems
+            )
+        } else {
+            "The config doesn't parse, edit again? (no throws the changes away)".to_string()
+        };
+        match Confirm::new(&prompt).with_default(true).prompt() {
+            Ok(true) => continue,
+            Ok(false) if can_save => break Some(content),
+            _ => break None,
+        }
+    };
+    drop(scratch_dir);
+
+    match edited {
+        Some(content) if content == original => println!("No changes."),
+        Some(content) => match changes::write(&path, &content) {
+            Ok(()) => println!("Saved {}", path.display()),
+            Err(err) => eprintln!("Error writing config: {}", err),
+        },
+        None => println!("Config left as it was."),
+    }
+}
+
+pub fn handle_config(config_args: ConfigArgs) {
+    match config_args.action {
+        ConfigActions::Check => config_check(),
+        ConfigActions::Path => match get_config_path() {
+            Some(path) => println!("{}", path.display()),
+            None => eprintln!("Couldn't find your home directory."),
+        },
+        ConfigActions::Edit => config_edit(),
+    }
+}
+
+/// The API for `user`'s host, asking for a token unless TILB_TOKEN has one
+fn resolve_provider(user: &User, provider_args: &ProviderArgs) -> Option<Provider> {
+    let Some(kind) = provider_args
+        .provider
+        .or_else(|| ProviderKind::detect(&user.git_host))
+    else {
+        eprintln!(
+            "Can't tell which API {} has, pass --provider github, gitlab or gitea",
+            user.git_host.bright_red()
+        );
+        return None;
+    };
+    let api_url = provider_args
+        .api_url
+        .clone()
+        .unwrap_or_else(|| kind.default_api_url(&user.git_host));
+
+    let token = match std::env::var("TILB_TOKEN") {
+        Ok(token) if !token.is_empty() => token,
+        _ => match Password::new(&format!(
+            "{} token for {} (needs access to your ssh keys):",
+            kind.name(),
+            user.username
+        ))
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .without_confirmation()
+        .prompt()
+        {
+            Ok(token) if !token.trim().is_empty() => token.trim().to_string(),
+            _ => {
+                println!("see ya (¯꒳¯)ᐝ");
+                return None;
+            }
+        },
+    };
+
+    Some(Provider::new(kind, api_url, token))
+}
+
+/// Uploads `public_key` for auth, and for signing if the user signs, printing how it went
+fn upload_public_key(key: &str, user: &User, provider: &Provider, public_key: &str) -> bool {
+    let mut usages = vec![KeyUsage::Auth];
+    if user.sign_commits {
+        usages.push(KeyUsage::Signing);
+    }
+
+    match provider.upload_key(&format!("tilb {}", key), public_key, &usages) {
+        Ok(uploaded) if uploaded.is_empty() => {
+            println!(
+                "The key of {} is already on the {} account",
+                key.green(),
+                provider.kind.name()
+            );
+            true
+        }
+        Ok(uploaded) => {
+            let labels: Vec<&str> = uploaded.iter().map(|usage| usage.label()).collect();
+            if !is_dry_run() {
+                println!(
+                    "Added the key of {} to the {} account as {} key",
+                    key.green(),
+                    provider.kind.name(),
+                    labels.join(" and ")
+                );
+            }
+            true
+        }
+        Err(err) => {
+            eprintln!("Error uploading key: {}", err);
+            false
+        }
+    }
+}
+
+fn read_public_key(key: &str, user: &User) -> Option<String> {
+    if user.use_https {
+        eprintln!("{} uses https, so has no ssh key", key.bright_red());
+        return None;
+    }
+    let path = get_key_path(&user.username)
+        .expect("no home dir")
+        .with_extension("pub");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Some(content.trim().to_string()),
+        Err(err) => {
+            eprintln!("Error reading {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Logs in over ssh as `key` and checks the host greets the right account
+fn verify_user(key: &str, user: &User) -> bool {
+    if user.use_https {
+        eprintln!(
+            "{} uses https, there's no ssh key to verify",
+            key.bright_red()
+        );
+        return false;
+    }
+
+    let alias = get_host_alias(&user.username);
+    let output = match ssh_greeting(&alias) {
+        Ok(output) => output,
+        Err(err) => {
+            eprintln!("Error running ssh: {}", err);
+            return false;
+        }
+    };
+
+    match parse_greeting(&output) {
+        Some(Greeting::Account(account)) if account.eq_ignore_ascii_case(&user.username) => {
+            println!(
+                "The key of {} logs in as {} on {}",
+                key.green(),
+                account.green(),
+                user.git_host
+            );
+            true
+        }
+        Some(Greeting::Account(account)) => {
+            eprintln!(
+                "The key of {} logs in as {} on {}, not {}",
+                key.bright_red(),
+                account.bright_red(),
+                user.git_host,
+                user.username
+            );
+            false
+        }
+        Some(Greeting::DeployKey(repo)) => {
+            eprintln!(
+                "The key of {} is a deploy key for {}, not a key on {}'s account",
+                key.bright_red(),
+                repo.bright_red(),
+                user.username
+            );
+            false
+        }
+        Some(Greeting::Denied) => {
+            eprintln!(
+                "{} turned the key of {} down, is it added to the account?",
+                user.git_host,
+                key.bright_red()
+            );
+            false
+        }
+        None => {
+            eprintln!(
+                "Couldn't tell who {} logs in as, ssh said:\n{}",
+                alias.bright_red(),
+                output.trim_end()
+            );
+            false
+        }
+    }
+}
+
+pub fn handle_verify(user_args: UserArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+    let Some(key) = resolve_user(&config, user_args.user, "Which user to verify?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+    let Some(user) = config.users.get(&key) else {
+        eprintln!("User '{}' not found in config.", key);
+        return;
+    };
+
+    if !verify_user(&key, user) {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the public key, then its fingerprint and randomart to check it by eye
+fn print_public_key(username: &str, public_key: &str) {
+    println!("{}", public_key);
+    if let Some(randomart) =
+        get_key_path(username).and_then(|path| get_key_randomart(&path.with_extension("pub")))
+    {
+        println!("{}", randomart);
+    }
+}
+
+fn copy_public_key(public_key: &str) {
+    match copy_to_clipboard(public_key) {
+        // a status line, kept out of whatever stdout is piped into
+        Ok(tool) => eprintln!("Copied the public key to the clipboard with {}", tool),
+        Err(err) => eprintln!("Error copying to the clipboard: {}", err),
+    }
+}
+
+fn key_show(show_args: KeyShowArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+    let Some(key) = resolve_user(&config, show_args.user, "Whose key to show?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+    let Some(user) = config.users.get(&key) else {
+        eprintln!("User '{}' not found in config.", key);
+        return;
+    };
+    let Some(public_key) = read_public_key(&key, user) else {
+        return;
+    };
+
+    print_public_key(&user.username, &public_key);
+    if show_args.copy {
+        copy_public_key(&public_key);
+    }
+}
+
+fn key_upload(key_args: KeyRemoteArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+    let Some(key) = resolve_user(&config, key_args.user, "Whose key to upload?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+    let Some(user) = config.users.get(&key) else {
+        eprintln!("User '{}' not found in config.", key);
+        return;
+    };
+    let Some(public_key) = read_public_key(&key, user) else {
+        return;
+    };
+    let Some(provider) = resolve_provider(user, &key_args.provider) else {
+        return;
+    };
+
+    if !upload_public_key(&key, user, &provider, &public_key) {
+        std::process::exit(1);
+    }
+}
+
+fn key_list(key_args: KeyRemoteArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+    let Some(key) = resolve_user(&config, key_args.user, "Whose account to look at?") else {
+        println!("see ya (¯꒳¯)ᐝ");
+        return;
+    };
+    let Some(user) = config.users.get(&key) else {
+        eprintln!("User '{}' not found in config.", key);
+        return;
+    };
+    let Some(provider) = resolve_provider(user, &key_args.provider) else {
+        return;
+    };
+
+    let remote_keys = match provider.list_keys() {
+        Ok(remote_keys) => remote_keys,
+        Err(err) => {
+            eprintln!("Error listing keys: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if remote_keys.is_empty() {
+        println!("No ssh keys on the {} account.", provider.kind.name());
+        return;
+    }
+
+    // only compare against the local key quietly, https users have none
+    let local_key = (!user.use_https)
+        .then(|| {
+            get_key_path(&user.username)
+                .expect("no home dir")
+                .with_extension("pub")
+        })
+        .and_then(|path| std::fs::read_to_string(path).ok());
+
+    for remote_key in &remote_keys {
+        let usage: Vec<&str> = remote_key.usage.iter().map(|usage| usage.label()).collect();
+        let is_local = local_key
+            .as_deref()
+            .is_some_and(|local| same_public_key(local, &remote_key.key));
+        let title = if is_local {
+            remote_key.title.green().to_string()
+        } else {
+            remote_key.title.clone()
+        };
+        println!(
+            "{}  {} ({}){}",
+            remote_key.id,
+            title,
+            usage.join(", "),
+            if is_local { "  <- this user's key" } else { "" }
+        );
+    }
+}
+
+pub fn handle_key(key_args: KeyArgs) {
+    match key_args.action {
+        KeyActions::Show(show_args) => key_show(show_args),
+        KeyActions::Upload(remote_args) => key_upload(remote_args),
+        KeyActions::List(remote_args) => key_list(remote_args),
+    }
+}
+
+/// The templates at the config's `templates` path, printing why when there are none
+fn read_templates(config: &UsersConfig) -> Option<BTreeMap<String, LoadedTemplate>> {
+    let Some(location) = &config.templates else {
+        eprintln!(
+            "No templates set up, point `templates` in config.toml at a file or directory (see `tilb config edit`)"
+        );
+        return None;
+    };
+
+    match load_templates(location) {
+        Ok(templates) => Some(templates),
+        Err(err) => {
+            eprintln!("Error reading templates from {}: {}", location, err);
+            None
+        }
+    }
+}
+
+fn find_template(name: &str) -> Option<Template> {
+    let config = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            return None;
+        }
+    };
+    let mut templates = read_templates(&config)?;
+
+    match templates.remove(name) {
+        Some(loaded) => Some(loaded.template),
+        None => {
+            eprintln!("Template '{}' not found.", name);
+            None
+        }
+    }
+}
+
+/// Swaps the rules a template gave `user` for `rules`, after the user's own
+/// rules so those still win
+fn replace_template_rules(config: &mut UsersConfig, user: &str, rules: Vec<Rule>) {
+    config
+        .rules
+        .retain(|rule| rule.user != user || rule.template.is_none());
+    config.rules.extend(rules);
+}
+
+fn describe_user_changes(old: &User, new: &User) -> Vec<String> {
+    let transport = |user: &User| if user.use_https { "https" } else { "ssh" };
+    let mut changes = Vec::new();
+
+    if old.git_host != new.git_host {
+        changes.push(format!("host: {} -> {}", old.git_host, new.git_host));
+    }
+    if old.use_https != new.use_https {
+        changes.push(format!(
+            "transport: {} -> {}",
+            transport(old),
+            transport(new)
+        ));
+    }
+    if old.sign_commits != new.sign_commits {
+        changes.push(format!(
+            "sign commits: {} -> {}",
+            old.sign_commits, new.sign_commits
+        ));
+    }
+    if old.default_branch != new.default_branch {
+        changes.push(format!(
+            "default branch: {} -> {}",
+            old.default_branch.as_deref().unwrap_or("(git's)"),
+            new.default_branch.as_deref().unwrap_or("(git's)")
+        ));
+    }
+    if old.ssh_options != new.ssh_options {
+        changes.push("ssh options updated".to_string());
+    }
+    changes
+}
+
+fn template_list() {
+    // templates are most useful before there are any users
+    let config = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            return;
+        }
+    };
+    let Some(templates) = read_templates(&config) else {
+        return;
+    };
+
+    if templates.is_empty() {
+        println!("No templates found.");
+        return;
+    }
+
+    for (name, loaded) in &templates {
+        let template = &loaded.template;
+        let mut users: Vec<&str> = config
+            .users
+            .iter()
+            .filter(|(_, user)| user.template.as_deref() == Some(name.as_str()))
+            .map(|(key, _)| key.as_str())
+            .collect();
+        users.sort();
+
+        println!(
+            "{} {} ({}), from {}",
+            name.green(),
+            template.git_host,
+            if template.use_https { "https" } else { "ssh" },
+            loaded.source.display()
+        );
+        if !users.is_empty() {
+            println!("    used by {}", users.join(", "));
+        }
+    }
+}
+
+fn template_sync(user: Option<String>) {
+    let Some(mut config) = load_config() else {
+        return;
+    };
+
+    let mut keys: Vec<String> = match user {
+        Some(user) => match config.users.get(&user) {
+            Some(found) if found.template.is_some() => vec![user],
+            Some(_) => {
+                eprintln!("User '{}' wasn't made from a template.", user);
+                return;
+            }
+            None => {
+                eprintln!("User '{}' not found in config.", user);
+                return;
+            }
+        },
+        None => config
+            .users
+            .iter()
+            .filter(|(_, user)| user.template.is_some())
+            .map(|(key, _)| key.clone())
+            .collect(),
+    };
+    keys.sort();
+
+    if keys.is_empty() {
+        println!("No users were made from a template.");
+        return;
+    }
+
+    let Some(templates) = read_templates(&config) else {
+        return;
+    };
+
+    let mut changed_any = false;
+    for key in keys {
+        let old = config.users[&key].clone();
+        let name = old.template.clone().unwrap_or_default();
+        let Some(loaded) = templates.get(&name) else {
+            eprintln!(
+                "Template '{}' for {} no longer exists, skipped.",
+                name,
+                key.bright_red()
+            );
+            continue;
+        };
+        let template = &loaded.template;
+
+        let mut new = old.clone();
+        template.apply_to(&mut new);
+
+        let mut changes = describe_user_changes(&old, &new);
+        let rules_before = config.rules.clone();
+        replace_template_rules(&mut config, &key, template.rules_for(&name, &key));
+        if config.rules != rules_before {
+            changes.push("rules updated".to_string());
+        }
+
+        if changes.is_empty() {
+            println!("{} is up to date with {}", key.green(), name);
+            continue;
+        }
+        changed_any = true;
+
+        let alias = get_host_alias(&new.username);
+        let result = if new.use_https {
+            if old.use_https {
+                Ok(())
+            } else {
+                remove_from_ssh_config(&alias)
+            }
+        } else {
+            let key_path = get_key_path(&new.username).expect("no home dir");
+            if !key_path.exists() {
+                eprintln!(
+                    "{} has no ssh key at {}, run `tilb add` again to make one",
+                    key.bright_red(),
+                    key_path.display()
+                );
+            }
+            add_to_ssh_config(
+                &alias,
+                &new.git_host,
+                "git",
+                &key_path.display().to_string(),
+                &new.ssh_options,
+            )
+        };
+        if let Err(err) = result {
+            eprintln!("Error updating ssh config: {}", err);
+            return;
+        }
+
+        println!("{} synced with {}", key.green(), name);
+        for change in changes {
+            println!("    {}", change);
+        }
+        config.users.insert(key, new);
+    }
+
+    if !changed_any {
+        return;
+    }
+
+    config.version = CONFIG_VERSION;
+    if let Err(err) = write_config_file(&config) {
+        eprintln!("Error updating config: {}", err);
+        return;
+    }
+    println!(
+        "Repos already switched keep their old settings until switched again, e.g. `tilb switch --auto --recursive <dir>`"
+    );
+}
+
+pub fn handle_template(template_args: TemplateArgs) {
+    match template_args.action {
+        TemplateActions::List => template_list(),
+        TemplateActions::Sync(sync_args) => template_sync(sync_args.user),
+    }
+}
+
+/// A copy of a user's private key encrypted with `passphrase`, `None` when they have no key
+fn export_key(
+    name: &str,
+    passphrase: &str,
+) -> Result<Option<BundledKey>, Box<dyn std::error::Error>> {
+    let Some(key_path) = get_key_path(name) else {
+        return Ok(None);
+    };
+    if !key_path.exists() {
+        return Ok(None);
+    }
+
+    let public = std::fs::read_to_string(key_path.with_extension("pub"))?;
+    let private = std::fs::read_to_string(&key_path)?;
+
+    // re-encrypt a scratch copy, the key in ~/.ssh/tilb stays as it is
+    let scratch_dir = ScratchDir::new("tilb-export")?;
+    let scratch = scratch_dir.create_file("key", &private)?;
+    let encrypted = (|| -> Result<String, Box<dyn std::error::Error>> {
+        // a key without a passphrase of its own takes the new one straight away
+        if change_key_passphrase(&scratch, "", passphrase).is_err() {
+            let old = Password::new(&format!("Passphrase of {}'s key:", name))
+                .with_display_mode(PasswordDisplayMode::Masked)
+                .without_confirmation()
+                .prompt()?;
+            change_key_passphrase(&scratch, &old, passphrase)?;
+        }
+        Ok(std::fs::read_to_string(&scratch)?)
+    })();
+    drop(scratch_dir);
+
+    Ok(Some(BundledKey {
+        public: public.trim().to_string(),
+        private: encrypted?,
+    }))
+}
+
+pub fn handle_export(export_args: ExportArgs) {
+    let Some(config) = load_config() else {
+        return;
+    };
+
+    let mut bundle = Bundle {
+        version: BUNDLE_VERSION,
+        users: config.users.into_iter().collect(),
+        rules: config.rules,
+        keys: Default::default(),
+    };
+
+    // options added to a user's ssh block by hand go along, import writes them back
+    for user in bundle.users.values_mut().filter(|user| !user.use_https) {
+        for (key, value) in get_block_options(&get_host_alias(&user.username)) {
+            if !user
+                .ssh_options
+                .keys()
+                .any(|existing| existing.eq_ignore_ascii_case(&key))
+            {
+                user.ssh_options.insert(key, value);
+            }
+        }
+    }
+
+    if export_args.with_keys {
+        let passphrase = match Password::new("Passphrase to encrypt the keys with:")
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .with_validator(|input: &str| {
+                if input.is_empty() {
+                    Ok(Validation::Invalid(
+                        "Keys are never exported unencrypted".into(),
+                    ))
+                } else {
+                    Ok(Validation::Valid)
+                }
+            })
+            .prompt()
+        {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                println!("see ya (¯꒳¯)ᐝ");
+                return;
+            }
+        };
+
+        for (key, user) in &bundle.users {
+            if user.use_https {
+                continue;
+            }
+            match export_key(&user.username, &passphrase) {
+                Ok(Some(bundled)) => {
+                    bundle.keys.insert(key.clone(), bundled);
+                }
+                Ok(None) => eprintln!("No ssh key found for {}, exporting it without", key),
+                Err(err) => {
+                    eprintln!("Error exporting the key of {}: {}", key, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    let content = match bundle_to_string(&bundle) {
+        Ok(content) => content,
+        Err(err) => {
+            eprintln!("Error writing bundle: {}", err);
+            return;
+        }
+    };
+
+    let Some(output) = export_args.output else {
+        print!("{}", content);
+        return;
+    };
+    // a bundle with keys stays out of the journal like the keys themselves
+    let written = if bundle.keys.is_empty() {
+        changes::write(&output, &content)
+    } else if is_dry_run() {
+        changes::record_command(format!("write bundle {}", output.display()));
+        Ok(())
+    } else {
+        write_secret_file(&output, &content)
+            .map(|()| changes::record_command(format!("write bundle {}", output.display())))
+    };
+    match written {
+        Ok(()) if is_dry_run() => println!(
+            "Would export {} user(s) and {} key(s) to {}",
+            bundle.users.len(),
+            bundle.keys.len(),
+            output.display().to_string().green()
+        ),
+        Ok(()) => println!(
+            "Exported {} user(s) and {} key(s) to {}",
+            bundle.users.len(),
+            bundle.keys.len(),
+            output.display().to_string().green()
+        ),
+        Err(err) => eprintln!("Error writing {}: {}", output.display(), err),
+    }
+}
+
+/// Whether to take `theirs` over the user already called `key` here
+fn take_bundled_user(key: &str, ours: &User, theirs: &User, on_conflict: OnConflict) -> bool {
+    match on_conflict {
+        OnConflict::Keep => false,
+        OnConflict::Replace => true,
+        OnConflict::Ask => {
+            let describe = |user: &User| {
+                format!(
+                    "{} <{}> on {}{}",
+                    user.username,
+                    user.email,
+                    user.git_host,
+                    if user.use_https { " over https" } else { "" }
+                )
+            };
+            println!("{} differs from the bundle:", key.yellow());
+            println!("    here:   {}", describe(ours));
+            println!("    bundle: {}", describe(theirs));
+            let options = vec!["Keep mine", "Take the bundle's"];
+            Select::new(&format!("Which {} to keep?", key), options)
+                .prompt()
+                .is_ok_and(|choice| choice == "Take the bundle's")
+        }
+    }
+}
+
+/// Writes a bundled key to where `name`'s key goes, re-encrypted from `bundle_passphrase` to `passphrase`
+fn import_key(
+    name: &str,
+    bundled: &BundledKey,
+    bundle_passphrase: &str,
+    passphrase: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let key_path = get_key_path(name).ok_or("no home dir")?;
+    if !is_dry_run() {
+        write_secret_file(&key_path, &bundled.private)?;
+        if let Err(err) = change_key_passphrase(&key_path, bundle_passphrase, passphrase) {
+            let _ = std::fs::remove_file(&key_path);
+            return Err(format!("couldn't decrypt it, wrong passphrase? ({})", err).into());
+        }
+    }
+    changes::record_command(format!("write ssh key {}", key_path.display()));
+    changes::write(
+        &key_path.with_extension("pub"),
+        &format!("{}\n", bundled.public),
+    )?;
+    Ok(())
+}
+
+pub fn handle_import(import_args: ImportArgs) {
+    let bundle = match read_bundle(&import_args.bundle) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            eprintln!("Error reading {}: {}", import_args.bundle.display(), err);
+            return;
+        }
+    };
+    let mut config = match read_config_file() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error reading config: {}", err);
+            return;
+        }
+    };
+
+    // users whose settings end up being the bundle's, so its key and ssh block apply
+    let mut taken: Vec<&str> = Vec::new();
+    let mut changed = false;
+    for (key, user) in &bundle.users {
+        match config.users.get(key) {
+            Some(ours) if ours == user => taken.push(key),
+            Some(ours) if !take_bundled_user(key, ours, user, import_args.on_conflict) => {
+                println!("Kept {} as it is", key);
+            }
+            existing => {
+                println!(
+                    "{} {}",
+                    if existing.is_some() {
+                        "Replaced"
+                    } else {
+                        "Added"
+                    },
+                    key.green()
+                );
+                config.users.insert(key.clone(), user.clone());
+                taken.push(key);
+                changed = true;
+            }
+        }
+    }
+
+    let mut new_rules = 0;
+    for rule in &bundle.rules {
+        if !config.rules.contains(rule) {
+            config.rules.push(rule.clone());
+            new_rules += 1;
+        }
+    }
+    if new_rules > 0 {
+        println!("Added {} rule(s)", new_rules);
+        changed = true;
+    }
+
+    // a key already here is never overwritten, it may be the only copy
+    let mut keys_to_write = Vec::new();
+    for key in &taken {
+        let (Some(bundled), Some(key_path)) = (
+            bundle.keys.get(*key),
+            get_key_path(&bundle.users[*key].username),
+        ) else {
+            continue;
+        };
+        if !key_path.exists() {
+            keys_to_write.push((*key, bundled));
+        } else if std::fs::read_to_string(key_path.with_extension("pub"))
+            .is_ok_and(|public| public.trim() != bundled.public.trim())
+        {
+            eprintln!(
+                "Kept the different key already at {}, {}'s bundled key wasn't written",
+                key_path.display(),
+                key
+            );
+        }
+    }
+
+    if !keys_to_write.is_empty() {
+        let bundle_passphrase = match Password::new("Passphrase the keys were exported with:")
+            .with_display_mode(PasswordDisplayMode::Masked)
+            .without_confirmation()
+            .prompt()
+        {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                println!("see ya (¯꒳¯)ᐝ");
+                return;
+            }
+        };
+        let passphrase = match Password::new(
+            "Passphrase for the imported keys (leave empty for no passphrase):",
+        )
+        .with_display_mode(PasswordDisplayMode::Masked)
+        .prompt()
+        {
+            Ok(passphrase) => passphrase,
+            Err(_) => {
+                println!("see ya (¯꒳¯)ᐝ");
+                return;
+            }
+        };
+
+        for (key, bundled) in keys_to_write {
+            if let Err(err) = import_key(
+                &bundle.users[key].username,
+                bundled,
+                &bundle_passphrase,
+                &passphrase,
+            ) {
+                eprintln!("Error importing the key of {}: {}", key, err);
+                return;
+            }
+            println!("Wrote the ssh key of {}", key.green());
+        }
+    }
+
+    if changed {
+        config.version = CONFIG_VERSION;
+        if let Err(err) = write_config_file(&config) {
+            eprintln!("Error updating config: {}", err);
+            return;
+        }
+    }
+
+    for key in taken {
+        let user = &bundle.users[key];
+        if user.use_https {
+            continue;
+        }
+        let ssh_path = get_key_path(&user.username)
+            .expect("no home dir")
+            .display()
+            .to_string();
+        if let Err(err) = add_to_ssh_config(
+            &get_host_alias(&user.username),
+            &user.git_host,
+            "git",
+            &ssh_path,
+            &user.ssh_options,
+        ) {
+            eprintln!("Error updating ssh config: {}", err);
+            return;
+        }
+    }
+
+    println!(
+        "Imported {}",
+        import_args.bundle.display().to_string().green()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_keys_normalize_like_git() {
+        assert_eq!(normalize_git_key("Core.AutoCRLF"), "core.autocrlf");
+        assert_eq!(
+            normalize_git_key("URL.git@Host:.insteadOf"),
+            "url.git@Host:.insteadof"
+        );
+        assert_eq!(
+            normalize_git_key("branch.Feature/X.Remote"),
+            "branch.Feature/X.remote"
+        );
+        assert_eq!(
+            normalize_git_key("url.a.b.c.pushInsteadOf"),
+            "url.a.b.c.pushinsteadof"
+        );
     }
 }
@@ -0,0 +1,125 @@
+use std::{
+    env, fs,
+    io::Result,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use directories::UserDirs;
+
+/*
+where tilb keeps its files, first match wins:
+
+1. $TILB_HOME
+2. $XDG_CONFIG_HOME/tilb, or ~/.config/tilb, on every platform
+3. ~/.tilb, from before tilb knew about XDG, until `migrate_legacy_home` moves it
+
+--config only swaps out config.toml, state and journal stay in the home.
+keys go to $TILB_SSH_DIR, or ~/.ssh/tilb. the ssh blocks pointing at them
+always go to ~/.ssh/config, the one file ssh reads
+*/
+
+static CONFIG_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses `path` as config.toml for the rest of the run, from `--config`
+pub fn set_config_override(path: PathBuf) {
+    let _ = CONFIG_OVERRIDE.set(path);
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+fn get_legacy_home() -> Option<PathBuf> {
+    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".tilb"))
+}
+
+// not BaseDirs::config_dir, that's ~/Library/Application Support on macos
+fn get_xdg_home() -> Option<PathBuf> {
+    env_path("XDG_CONFIG_HOME")
+        .or_else(|| UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".config")))
+        .map(|dir| dir.join("tilb"))
+}
+
+pub fn get_tilb_home() -> Option<PathBuf> {
+    if let Some(home) = env_path("TILB_HOME") {
+        return Some(home);
+    }
+
+    let xdg = get_xdg_home()?;
+    match get_legacy_home() {
+        // only when the move failed, or hasn't happened yet in a dry run
+        Some(legacy) if legacy.is_dir() && !xdg.exists() => Some(legacy),
+        _ => Some(xdg),
+    }
+}
+
+pub fn get_config_path() -> Option<PathBuf> {
+    if let Some(path) = CONFIG_OVERRIDE.get() {
+        return Some(path.clone());
+    }
+    get_tilb_home().map(|home| home.join("config.toml"))
+}
+
+pub fn get_state_path() -> Option<PathBuf> {
+    get_tilb_home().map(|home| home.join("state.toml"))
+}
+
+pub fn get_journal_path() -> Option<PathBuf> {
+    get_tilb_home().map(|home| home.join("journal.toml"))
+}
+
+pub fn get_global_hooks_dir() -> Option<PathBuf> {
+    get_tilb_home().map(|home| home.join("hooks"))
+}
+
+pub fn get_ssh_dir_path() -> Option<PathBuf> {
+    if let Some(dir) = env_path("TILB_SSH_DIR") {
+        return Some(dir);
+    }
+    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".ssh").join("tilb"))
+}
+
+// ssh only reads ~/.ssh/config, so TILB_SSH_DIR doesn't move this
+pub fn get_ssh_config_path() -> Option<PathBuf> {
+    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".ssh").join("config"))
+}
+
+/// Moves ~/.tilb to the XDG location, returning where it went if it moved.
+/// Does nothing when TILB_HOME is set or the XDG dir already exists
+pub fn migrate_legacy_home() -> Result<Option<(PathBuf, PathBuf)>> {
+    if env_path("TILB_HOME").is_some() {
+        return Ok(None);
+    }
+    let (Some(legacy), Some(xdg)) = (get_legacy_home(), get_xdg_home()) else {
+        return Ok(None);
+    };
+    if !legacy.is_dir() || xdg.exists() {
+        return Ok(None);
+    }
+
+    if let Some(parent) = xdg.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&legacy, &xdg)?;
+    Ok(Some((legacy, xdg)))
+}
+
+/// ~/.tilb and the XDG dir, when both exist and the former is being ignored
+pub fn get_ignored_legacy_home() -> Option<(PathBuf, PathBuf)> {
+    if env_path("TILB_HOME").is_some() {
+        return None;
+    }
+    let (legacy, xdg) = (get_legacy_home()?, get_xdg_home()?);
+    (legacy.is_dir() && xdg.exists()).then_some((legacy, xdg))
+}
+
+/// `path` moved along with its home, for paths recorded before a migration
+pub fn rebase_path(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(rest) => to.join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,557 @@
+use std::io::{Error, Result, Write};
+use std::process::{Command, Stdio};
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+use crate::changes;
+
+/*
+talks to the git host's REST API to put a user's public key on their
+account, so `tilb add` doesn't end with copy pasting it into a browser.
+
+requests go through curl, like everything else tilb shells out for. the
+token and body are handed over on stdin as a curl config (--config -),
+so the token never shows up in `ps`. --api-url points a provider at
+another server, a self-hosted instance or a mock for testing
+
+                 auth keys            signing keys
+github           /user/keys           /user/ssh_signing_keys
+gitlab           /user/keys           /user/keys with usage_type
+gitea, forgejo   /user/keys           same keys, gitea verifies ssh
+                                      signatures against them
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProviderKind {
+    Github,
+    Gitlab,
+    /// Also Forgejo, e.g. codeberg.org
+    Gitea,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyUsage {
+    Auth,
+    Signing,
+}
+
+impl KeyUsage {
+    pub fn label(self) -> &'static str {
+        match self {
+            KeyUsage::Auth => "auth",
+            KeyUsage::Signing => "signing",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteKey {
+    pub id: String,
+    pub title: String,
+    pub key: String,
+    pub usage: Vec<KeyUsage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub kind: ProviderKind,
+    pub api_url: String,
+    token: String,
+}
+
+impl ProviderKind {
+    /// Guesses the provider from the host, for the well-known ones
+    pub fn detect(host: &str) -> Option<ProviderKind> {
+        match host {
+            "github.com" => Some(ProviderKind::Github),
+            "gitlab.com" => Some(ProviderKind::Gitlab),
+            "codeberg.org" | "gitea.com" | "gitea.io" => Some(ProviderKind::Gitea),
+            _ => None,
+        }
+    }
+
+    pub fn default_api_url(self, host: &str) -> String {
+        match self {
+            ProviderKind::Github if host == "github.com" => "https://api.github.com".to_string(),
+            // github enterprise server
+            ProviderKind::Github => format!("https://{}/api/v3", host),
+            ProviderKind::Gitlab => format!("https://{}/api/v4", host),
+            ProviderKind::Gitea => format!("https://{}/api/v1", host),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Github => "GitHub",
+            ProviderKind::Gitlab => "GitLab",
+            ProviderKind::Gitea => "Gitea",
+        }
+    }
+}
+
+/// `type base64` of an openssh public key, leaving out the comment hosts may rewrite
+fn key_body(key: &str) -> String {
+    key.split_whitespace().take(2).collect::<Vec<_>>().join(" ")
+}
+
+/// Whether two openssh public keys are the same key, whatever their comments
+pub fn same_public_key(a: &str, b: &str) -> bool {
+    key_body(a) == key_body(b)
+}
+
+/// Quotes `value` for a curl config file
+fn curl_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// What the API said went wrong, from the usual `message` field when there is one
+fn error_message(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(Value::Object(map)) => match map.get("message") {
+            Some(Value::String(message)) => message.clone(),
+            Some(other) => other.to_string(),
+            None => body.trim().to_string(),
+        },
+        _ => body.trim().to_string(),
+    }
+}
+
+/// Splits curl's output into the body and the status `--write-out "\n%{http_code}"` appended
+fn split_status(stdout: &str) -> (&str, u16) {
+    let (response, status) = stdout.rsplit_once('\n').unwrap_or(("", stdout));
+    (response, status.trim().parse().unwrap_or_default())
+}
+
+/// The usages in `usages` no key in `keys` matching `public_key` has yet
+fn missing_from(keys: &[RemoteKey], public_key: &str, usages: &[KeyUsage]) -> Vec<KeyUsage> {
+    let wanted = key_body(public_key);
+    usages
+        .iter()
+        .copied()
+        .filter(|usage| {
+            !keys
+                .iter()
+                .any(|key| key_body(&key.key) == wanted && key.usage.contains(usage))
+        })
+        .collect()
+}
+
+impl Provider {
+    pub fn new(kind: ProviderKind, api_url: String, token: String) -> Provider {
+        Provider {
+            kind,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn auth_header(&self) -> String {
+        match self.kind {
+            ProviderKind::Github => format!("Authorization: Bearer {}", self.token),
+            ProviderKind::Gitlab => format!("PRIVATE-TOKEN: {}", self.token),
+            ProviderKind::Gitea => format!("Authorization: token {}", self.token),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value> {
+        let url = format!("{}{}", self.api_url, path);
+
+        let mut config = vec![
+            format!("url = {}", curl_quote(&url)),
+            format!("request = {}", curl_quote(method)),
+            format!("header = {}", curl_quote(&self.auth_header())),
+            format!("header = {}", curl_quote("Accept: application/json")),
+            format!("user-agent = {}", curl_quote("tilb")),
+            "silent".to_string(),
+            "show-error".to_string(),
+            format!("write-out = {}", curl_quote("\n%{http_code}")),
+        ];
+        if self.kind == ProviderKind::Github {
+            config.push(format!(
+                "header = {}",
+                curl_quote("X-GitHub-Api-Version: 2022-11-28")
+            ));
+        }
+        if let Some(body) = body {
+            config.push(format!(
+                "header = {}",
+                curl_quote("Content-Type: application/json")
+            ));
+            config.push(format!("data-binary = {}", curl_quote(&body.to_string())));
+        }
+
+        let mut child = Command::new("curl")
+            .arg("--config")
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| Error::other(format!("couldn't run curl: {}", err)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(config.join("\n").as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::other(format!(
+                "{} {}: {}",
+                method,
+                url,
+                stderr.trim()
+            )));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (response, status) = split_status(&stdout);
+
+        if !(200..300).contains(&status) {
+            return Err(Error::other(format!(
+                "{} {} returned {}: {}",
+                method,
+                url,
+                status,
+                error_message(response)
+            )));
+        }
+
+        if response.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(response)
+            .map_err(|err| Error::other(format!("{} {} returned bad json: {}", method, url, err)))
+    }
+
+    fn parse_key(&self, value: &Value) -> Option<RemoteKey> {
+        let id = match value.get("id")? {
+            Value::String(id) => id.clone(),
+            other => other.to_string(),
+        };
+        let key = value.get("key")?.as_str()?.to_string();
+        let title = value
+            .get("title")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        let usage = match self.kind {
+            // older gitlab versions don't say, their keys are all auth keys
+            ProviderKind::Gitlab => match value.get("usage_type").and_then(Value::as_str) {
+                Some("signing") => vec![KeyUsage::Signing],
+                Some("auth_and_signing") => vec![KeyUsage::Auth, KeyUsage::Signing],
+                _ => vec![KeyUsage::Auth],
+            },
+            ProviderKind::Gitea => vec![KeyUsage::Auth, KeyUsage::Signing],
+            // github keeps them apart, `list_keys` fills this in
+            ProviderKind::Github => Vec::new(),
+        };
+
+        Some(RemoteKey {
+            id,
+            title,
+            key,
+            usage,
+        })
+    }
+
+    fn parse_keys(&self, value: &Value) -> Result<Vec<RemoteKey>> {
+        let Some(items) = value.as_array() else {
+            return Err(Error::other("expected a list of keys"));
+        };
+        Ok(items
+            .iter()
+            .filter_map(|item| self.parse_key(item))
+            .collect())
+    }
+
+    /// Every ssh key on the account, only the first 100 of each kind
+    pub fn list_keys(&self) -> Result<Vec<RemoteKey>> {
+        match self.kind {
+            ProviderKind::Github => {
+                let mut keys =
+                    self.parse_keys(&self.request("GET", "/user/keys?per_page=100", None)?)?;
+                for key in &mut keys {
+                    key.usage = vec![KeyUsage::Auth];
+                }
+                let mut signing = self.parse_keys(&self.request(
+                    "GET",
+                    "/user/ssh_signing_keys?per_page=100",
+                    None,
+                )?)?;
+                for key in &mut signing {
+                    key.usage = vec![KeyUsage::Signing];
+                }
+                keys.extend(signing);
+                Ok(keys)
+            }
+            ProviderKind::Gitlab => {
+                self.parse_keys(&self.request("GET", "/user/keys?per_page=100", None)?)
+            }
+            ProviderKind::Gitea => {
+                self.parse_keys(&self.request("GET", "/user/keys?limit=100", None)?)
+            }
+        }
+    }
+
+    /// The usages in `usages` no key on the account matching `public_key` has yet
+    fn missing_usages(&self, public_key: &str, usages: &[KeyUsage]) -> Result<Vec<KeyUsage>> {
+        Ok(missing_from(&self.list_keys()?, public_key, usages))
+    }
+
+    /// Adds `public_key` to the account for whichever of `usages` it isn't
+    /// there for yet, checks it's really there, and returns what it added
+    pub fn upload_key(
+        &self,
+        title: &str,
+        public_key: &str,
+        usages: &[KeyUsage],
+    ) -> Result<Vec<KeyUsage>> {
+        let keys = self.list_keys()?;
+        let missing = missing_from(&keys, public_key, usages);
+        if missing.is_empty() {
+            return Ok(missing);
+        }
+
+        let labels: Vec<&str> = missing.iter().map(|usage| usage.label()).collect();
+        changes::record_command(format!(
+            "upload {} key \"{}\" to {}",
+            labels.join(" and "),
+            title,
+            self.api_url
+        ));
+        if changes::is_dry_run() {
+            return Ok(missing);
+        }
+
+        let body = json!({ "title": title, "key": public_key });
+        match self.kind {
+            ProviderKind::Github => {
+                for usage in &missing {
+                    let path = match usage {
+                        KeyUsage::Auth => "/user/keys",
+                        KeyUsage::Signing => "/user/ssh_signing_keys",
+                    };
+                    self.request("POST", path, Some(&body))?;
+                }
+            }
+            ProviderKind::Gitlab => {
+                // the same key can't be added twice, and gitlab can't change a
+                // key's usage, so a key already there for one usage is replaced
+                // by one for both
+                let existing = keys
+                    .iter()
+                    .find(|key| same_public_key(&key.key, public_key));
+                let mut wanted = missing.clone();
+                if let Some(existing) = existing {
+                    wanted.extend(existing.usage.iter().copied());
+                }
+                let usage_type = match wanted.as_slice() {
+                    [usage] => usage.label(),
+                    _ => "auth_and_signing",
+                };
+                // the replacement keeps the name it was given on the account
+                let title = existing
+                    .map(|key| key.title.as_str())
+                    .filter(|title| !title.is_empty())
+                    .unwrap_or(title);
+                let body = json!({ "title": title, "key": public_key, "usage_type": usage_type });
+
+                if let Some(existing) = existing {
+                    self.request("DELETE", &format!("/user/keys/{}", existing.id), None)?;
+                    // gitlab won't take the key while the old one is there, so
+                    // if adding it back fails the old one is put back as it was
+                    if let Err(err) = self.request("POST", "/user/keys", Some(&body)) {
+                        let old_usage = match existing.usage.as_slice() {
+                            [usage] => usage.label(),
+                            _ => "auth_and_signing",
+                        };
+                        let old_body = json!({ "title": existing.title, "key": existing.key, "usage_type": old_usage });
+                        return Err(match self.request("POST", "/user/keys", Some(&old_body)) {
+                            Ok(_) => Error::other(format!(
+                                "couldn't add the key for {}, the old key was put back: {}",
+                                usage_type, err
+                            )),
+                            Err(restore_err) => Error::other(format!(
+                                "removed the key to add it back for {}, but adding it failed: {}, and putting the old key back failed too: {}",
+                                usage_type, err, restore_err
+                            )),
+                        });
+                    }
+                } else {
+                    self.request("POST", "/user/keys", Some(&body))?;
+                }
+            }
+            ProviderKind::Gitea => {
+                self.request("POST", "/user/keys", Some(&body))?;
+            }
+        }
+
+        if !self.missing_usages(public_key, &missing)?.is_empty() {
+            return Err(Error::other(format!(
+                "{} accepted the key but doesn't list it",
+                self.kind.name()
+            )));
+        }
+        Ok(missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKey me@laptop";
+
+    fn provider(kind: ProviderKind) -> Provider {
+        Provider::new(kind, "http://localhost/api/".to_string(), String::new())
+    }
+
+    fn remote(key: &str, usage: &[KeyUsage]) -> RemoteKey {
+        RemoteKey {
+            id: "1".to_string(),
+            title: "tilb me".to_string(),
+            key: key.to_string(),
+            usage: usage.to_vec(),
+        }
+    }
+
+    #[test]
+    fn keys_compare_without_comments() {
+        assert!(same_public_key(
+            KEY,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKey"
+        ));
+        assert!(same_public_key(
+            KEY,
+            "  ssh-ed25519  AAAAC3NzaC1lZDI1NTE5AAAAIKey other\n"
+        ));
+        assert!(!same_public_key(
+            KEY,
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOther"
+        ));
+    }
+
+    #[test]
+    fn api_url_loses_its_trailing_slash() {
+        assert_eq!(
+            provider(ProviderKind::Gitea).api_url,
+            "http://localhost/api"
+        );
+    }
+
+    #[test]
+    fn github_keys() {
+        let keys = provider(ProviderKind::Github)
+            .parse_keys(&json!([
+                { "id": 12, "key": KEY, "title": "laptop" },
+                { "id": 13, "key": "ssh-rsa AAAAB3 x" },
+                { "title": "no key" },
+            ]))
+            .unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].id, "12");
+        assert_eq!(keys[0].title, "laptop");
+        assert_eq!(keys[0].key, KEY);
+        assert!(keys[0].usage.is_empty());
+        assert_eq!(keys[1].title, "");
+    }
+
+    #[test]
+    fn gitlab_usage_types() {
+        let keys = provider(ProviderKind::Gitlab)
+            .parse_keys(&json!([
+                { "id": 1, "key": KEY, "usage_type": "auth" },
+                { "id": 2, "key": KEY, "usage_type": "signing" },
+                { "id": 3, "key": KEY, "usage_type": "auth_and_signing" },
+                { "id": 4, "key": KEY },
+            ]))
+            .unwrap();
+        let usages: Vec<_> = keys.iter().map(|key| key.usage.clone()).collect();
+        assert_eq!(
+            usages,
+            vec![
+                vec![KeyUsage::Auth],
+                vec![KeyUsage::Signing],
+                vec![KeyUsage::Auth, KeyUsage::Signing],
+                vec![KeyUsage::Auth],
+            ]
+        );
+    }
+
+    #[test]
+    fn gitea_keys_do_both() {
+        let keys = provider(ProviderKind::Gitea)
+            .parse_keys(&json!([{ "id": "abc", "key": KEY, "title": "t" }]))
+            .unwrap();
+        assert_eq!(keys[0].id, "abc");
+        assert_eq!(keys[0].usage, vec![KeyUsage::Auth, KeyUsage::Signing]);
+    }
+
+    #[test]
+    fn key_list_must_be_a_list() {
+        assert!(
+            provider(ProviderKind::Github)
+                .parse_keys(&json!({ "message": "Bad credentials" }))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            error_message(r#"{"message": "Bad credentials", "status": "401"}"#),
+            "Bad credentials"
+        );
+        // gitlab validation errors
+        assert_eq!(
+            error_message(r#"{"message": {"key": ["has already been taken"]}}"#),
+            r#"{"key":["has already been taken"]}"#
+        );
+        assert_eq!(
+            error_message(r#"{"error": "nope"}"#),
+            r#"{"error": "nope"}"#
+        );
+        assert_eq!(error_message("  Bad Gateway\n"), "Bad Gateway");
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(split_status("[]\n200"), ("[]", 200));
+        assert_eq!(
+            split_status("{\n  \"id\": 1\n}\n201"),
+            ("{\n  \"id\": 1\n}", 201)
+        );
+        assert_eq!(split_status("\n204"), ("", 204));
+        assert_eq!(split_status("204"), ("", 204));
+        assert_eq!(split_status("oops"), ("", 0));
+    }
+
+    #[test]
+    fn missing_usages() {
+        let both = [KeyUsage::Auth, KeyUsage::Signing];
+        assert_eq!(missing_from(&[], KEY, &both), both);
+        assert_eq!(
+            missing_from(&[remote(KEY, &[KeyUsage::Auth])], KEY, &both),
+            vec![KeyUsage::Signing]
+        );
+        assert_eq!(
+            missing_from(
+                &[
+                    remote("ssh-ed25519 AAAA other", &[KeyUsage::Signing]),
+                    remote(KEY, &[KeyUsage::Auth]),
+                ],
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKey renamed",
+                &both
+            ),
+            vec![KeyUsage::Signing]
+        );
+        assert!(missing_from(&[remote(KEY, &both)], KEY, &both).is_empty());
+    }
+}
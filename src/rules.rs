@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+
+use directories::UserDirs;
+use glob::{MatchOptions, Pattern};
+use log::debug;
+
+use crate::config::{Rule, UsersConfig};
+use crate::git::{parse_origin_host, parse_origin_url, parse_origin_user};
+use crate::ssh::get_host_alias;
+use crate::state::read_state_file;
+
+// rules live in config.toml next to the users, first match wins:
+//
+// [[rules]]
+// remote = "github.com/acme-corp/*"
+// user = "work"
+//
+// [[rules]]
+// path = "~/src/personal"
+// user = "me"
+//
+// a rule with both remote and path needs both to match
+
+#[derive(Debug, Clone)]
+pub struct RemoteInfo {
+    /// The real host, even when the url goes through a tilb ssh alias
+    pub host: String,
+    pub owner: String,
+    pub repo: String,
+    /// The tilb user the url authenticates as, when the url gives it away
+    pub user: Option<String>,
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+pub fn resolve_remote(config: &UsersConfig, url: &str) -> Option<RemoteInfo> {
+    let host = parse_origin_host(url)?;
+    let (owner, repo) = parse_origin_url(url)?;
+
+    // git@tilb-<username>:owner/repo
+    if let Some((key, user)) = config
+        .users
+        .iter()
+        .find(|(_, user)| get_host_alias(&user.username) == host)
+    {
+        return Some(RemoteInfo {
+            host: user.git_host.clone(),
+            owner,
+            repo,
+            user: Some(key.clone()),
+        });
+    }
+
+    // https://<user>@host/owner/repo
+    let url_user = parse_origin_user(url).and_then(|name| {
+        config
+            .users
+            .iter()
+            .find(|(_, user)| user.use_https && user.username == name && user.git_host == host)
+            .map(|(key, _)| key.clone())
+    });
+
+    // git@host:owner/repo, which `switch --global` may have pointed at a user's key
+    let user = url_user.or_else(|| {
+        let state = read_state_file().ok()?;
+        let current = state.global.current?;
+        (current.ssh_host.as_deref() == Some(host.as_str()) && !url.contains("://"))
+            .then_some(current.profile)
+            .flatten()
+    });
+
+    Some(RemoteInfo {
+        host,
+        owner,
+        repo,
+        user,
+    })
+}
+
+pub fn expand_home(pattern: &str) -> String {
+    match pattern.strip_prefix("~/") {
+        Some(rest) => match UserDirs::new() {
+            Some(user_dirs) => format!("{}/{}", user_dirs.home_dir().display(), rest),
+            None => pattern.to_string(),
+        },
+        None => pattern.to_string(),
+    }
+}
+
+fn remote_matches(pattern: &str, remote: &RemoteInfo) -> bool {
+    let Ok(pattern) = Pattern::new(pattern.trim_end_matches('/')) else {
+        return false;
+    };
+
+    let owner_path = format!("{}/{}", remote.host, remote.owner);
+    let repo_path = format!("{}/{}", owner_path, remote.repo);
+    pattern.matches_with(&owner_path, MATCH_OPTIONS)
+        || pattern.matches_with(&repo_path, MATCH_OPTIONS)
+}
+
+fn path_matches(pattern: &str, repo_path: &Path) -> bool {
+    let Ok(pattern) = Pattern::new(&expand_home(pattern)) else {
+        return false;
+    };
+
+    let repo_path: PathBuf = repo_path
+        .canonicalize()
+        .unwrap_or_else(|_| repo_path.to_path_buf());
+    // a pattern naming a parent directory covers everything below it too
+    repo_path
+        .ancestors()
+        .any(|dir| pattern.matches_path_with(dir, MATCH_OPTIONS))
+}
+
+fn rule_matches(rule: &Rule, repo_path: Option<&Path>, remote: Option<&RemoteInfo>) -> bool {
+    if rule.remote.is_none() && rule.path.is_none() {
+        return false;
+    }
+
+    let remote_ok = match (&rule.remote, remote) {
+        (None, _) => true,
+        (Some(pattern), Some(remote)) => remote_matches(pattern, remote),
+        (Some(_), None) => false,
+    };
+    let path_ok = match (&rule.path, repo_path) {
+        (None, _) => true,
+        (Some(pattern), Some(repo_path)) => path_matches(pattern, repo_path),
+        (Some(_), None) => false,
+    };
+
+    remote_ok && path_ok
+}
+
+/// The first rule that applies to a repo at `repo_path` with the given origin
+pub fn find_rule<'a>(
+    config: &'a UsersConfig,
+    repo_path: Option<&Path>,
+    remote: Option<&RemoteInfo>,
+) -> Option<&'a Rule> {
+    let rule = config
+        .rules
+        .iter()
+        .find(|rule| rule_matches(rule, repo_path, remote));
+    debug!("{:?} {:?} -> {:?}", repo_path, remote, rule);
+    rule
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> UsersConfig {
+        toml::from_str(
+            r#"
+            version = 2
+
+            [users.work]
+            username = "jdoe-acme"
+            email = "jdoe@acme.com"
+            git_host = "github.com"
+            use_https = false
+
+            [users.me]
+            username = "jdoe"
+            email = "jdoe@example.com"
+            git_host = "gitlab.com"
+            use_https = true
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn alias_remote_resolves_to_the_profile() {
+        // what `tilb switch work` points origin at
+        let remote = resolve_remote(&config(), "git@tilb-jdoe-acme:acme-corp/widgets").unwrap();
+        assert_eq!(remote.host, "github.com");
+        assert_eq!(remote.owner, "acme-corp");
+        assert_eq!(remote.repo, "widgets");
+        assert_eq!(remote.user.as_deref(), Some("work"));
+
+        // the profile key isn't an alias
+        let remote = resolve_remote(&config(), "git@tilb-work:acme-corp/widgets").unwrap();
+        assert_eq!(remote.host, "tilb-work");
+    }
+
+    #[test]
+    fn https_remote_resolves_to_the_profile() {
+        let remote = resolve_remote(&config(), "https://jdoe@gitlab.com/jdoe/dots").unwrap();
+        assert_eq!(remote.host, "gitlab.com");
+        assert_eq!(remote.user.as_deref(), Some("me"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    fs::{self, DirBuilder, OpenOptions},
+    io::{ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/*
+a private directory under the temp dir for files that shouldn't be seen by
+anyone else on the machine, e.g. a decrypted key on its way into an export
+or the config while it's being edited. the directory is made with mode 0700
+and a fresh name, failing rather than reusing anything already there, and
+files in it are created new with mode 0600, so a path someone else planted
+(or symlinked) beforehand is never written through. removed again on drop
+*/
+
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub fn new(prefix: &str) -> Result<ScratchDir> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        for attempt in 0..100u32 {
+            let path = std::env::temp_dir().join(format!(
+                "{}-{}-{:x}",
+                prefix,
+                std::process::id(),
+                nanos.wrapping_add(attempt)
+            ));
+            match builder.create(&path) {
+                Ok(()) => return Ok(ScratchDir { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(std::io::Error::other("couldn't create a scratch directory"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates `name` in the directory, only its owner can read it
+    pub fn create_file(&self, name: &str, content: &str) -> Result<PathBuf> {
+        let path = self.path.join(name);
+
+        let mut options = OpenOptions::new();
+        options.create_new(true).write(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&path)?.write_all(content.as_bytes())?;
+        Ok(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -1,60 +1,102 @@
-use directories::UserDirs;
+use glob::{MatchOptions, Pattern};
 use log::info;
+use regex::Regex;
+use std::collections::BTreeMap;
 use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::io::{Error, ErrorKind};
-use std::path::PathBuf;
-use std::process::Command;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::changes;
+use crate::paths::{get_ssh_config_path, get_ssh_dir_path};
+use crate::scratch::ScratchDir;
 
 /*
 config generation logic:
 ignore everything then add a new block at the end, starts with a marker comment like so
 
 #tilb generated
-Host github.com-username
-  HostAlias github.com
+Host tilb-username
+  HostName github.com
   User git
-  IdentityFile ~/.ssh/id_username_ed25519
+  IdentityFile ~/.ssh/tilb/id_username_ed25519
+  IdentitiesOnly yes
+
+`switch --global` writes the same kind of block with the host itself
+(e.g. Host github.com) so plain clone urls use that user's key. ssh takes
+the first value it finds, so a block goes above any user-written Host that
+also matches it instead of at the end.
+a user's ssh_options (usually from a template) go at the end of its block
 
 */
 
 // TODO:
 // uses include in ssh config rather than modifying main config
 
-fn get_ssh_config_path() -> Option<PathBuf> {
-    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".ssh").join("config"))
+pub fn get_key_path(user: &str) -> Option<PathBuf> {
+    get_ssh_dir_path().map(|dir| dir.join(format!("id_{}_ed25519", user)))
 }
 
-pub fn get_ssh_dir_path() -> Option<PathBuf> {
-    UserDirs::new().map(|user_dirs| user_dirs.home_dir().join(".ssh").join("tilb"))
+pub fn get_key_fingerprint(public_key_path: &Path) -> Option<String> {
+    let output = Command::new("ssh-keygen")
+        .arg("-lf")
+        .arg(public_key_path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // e.g. 256 SHA256:abc... user (ED25519)
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .nth(1)
+        .map(|s| s.to_string())
+}
+
+/// The fingerprint line and randomart, as `ssh-keygen -lvf` prints them
+pub fn get_key_randomart(public_key_path: &Path) -> Option<String> {
+    let output = Command::new("ssh-keygen")
+        .arg("-lvf")
+        .arg(public_key_path)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string(),
+    )
+}
+
+pub fn get_host_alias(user: &str) -> String {
+    format!("tilb-{}", user)
 }
 
 fn ssh_config_exists() -> bool {
-    get_ssh_config_path().map_or(false, |p| p.exists())
+    get_ssh_config_path().is_some_and(|p| p.exists())
 }
 
 fn ssh_dir_exists() -> bool {
-    get_ssh_dir_path().map_or(false, |p| p.exists())
+    get_ssh_dir_path().is_some_and(|p| p.exists())
 }
 
 fn create_ssh_config() -> Result<(), Error> {
-    let config_path =
-        get_ssh_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let config_path = get_ssh_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
     if !config_path.exists() {
-        if let Some(parent) = config_path.parent() {
-            fs::create_dir_all(parent)?; // ensure ~/.ssh exists
-        }
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&config_path)?;
+        changes::write(&config_path, "")?; // also creates ~/.ssh
     }
 
     Ok(())
 }
 
 fn create_ssh_dir() -> Result<(), Error> {
-    let dir_path = get_ssh_dir_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let dir_path = get_ssh_dir_path().ok_or_else(|| Error::other("no home dir"))?;
 
     if !dir_path.exists() {
         fs::create_dir_all(&dir_path)?;
@@ -68,98 +110,341 @@ pub fn generate_ssh_key(user: &str, passphrase: &str) -> Result<String, Error> {
         create_ssh_dir()?;
     }
 
-    let ssh_dir = get_ssh_dir_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
-
-    let private_key_path = ssh_dir.join(format!("id_{}_ed25519", user));
+    let private_key_path = get_key_path(user).ok_or_else(|| Error::other("no home dir"))?;
     let public_key_path = private_key_path.with_extension("pub");
 
+    changes::record_command(format!(
+        "ssh-keygen -t ed25519 -C {} -f {}",
+        user,
+        private_key_path.display()
+    ));
+    if changes::is_dry_run() {
+        return Ok(String::new());
+    }
+
+    if private_key_path.exists() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, move it away to make a new key",
+                private_key_path.display()
+            ),
+        ));
+    }
+
+    // made without a passphrase in a private dir, the passphrase goes on
+    // through askpass rather than -N, and the key is only moved in once done
+    let scratch_dir = ScratchDir::new("tilb-keygen")?;
+    let scratch = scratch_dir.path().join("key");
+
+    // quiet, the fingerprint and randomart are shown along with the key instead
     let status = Command::new("ssh-keygen")
+        .arg("-q")
         .arg("-t")
         .arg("ed25519")
         .arg("-C")
         .arg(user)
         .arg("-f")
-        .arg(&private_key_path)
+        .arg(&scratch)
         .arg("-N")
-        .arg(passphrase)
+        .arg("")
+        .stdin(Stdio::null())
         .status()?;
-
     if !status.success() {
-        return Err(Error::new(ErrorKind::Other, "SSH key generation failed"));
+        return Err(Error::other("SSH key generation failed"));
+    }
+    if !passphrase.is_empty() {
+        change_key_passphrase(&scratch, "", passphrase)?;
     }
 
-    let public_key_content = fs::read_to_string(&public_key_path)?;
+    let public_key_content = fs::read_to_string(scratch.with_extension("pub"))?;
+    write_secret_file(&private_key_path, &fs::read_to_string(&scratch)?)?;
+    fs::write(&public_key_path, &public_key_content)?;
     Ok(public_key_content.trim().to_string())
 }
 
+// set when tilb runs as ssh-keygen's askpass helper, see `answer_askpass`
+const ASKPASS_ENV: &str = "TILB_ASKPASS";
+const ASKPASS_OLD_ENV: &str = "TILB_ASKPASS_OLD";
+const ASKPASS_NEW_ENV: &str = "TILB_ASKPASS_NEW";
+
+/// Re-encrypts the private key at `path` from passphrase `old` to `new`, either may be empty.
+/// The passphrases reach ssh-keygen through tilb itself as its askpass helper rather than
+/// -P/-N, which anyone on the machine could read from `ps`
+pub fn change_key_passphrase(path: &Path, old: &str, new: &str) -> Result<(), Error> {
+    let output = Command::new("ssh-keygen")
+        .arg("-p")
+        .arg("-f")
+        .arg(path)
+        .env("SSH_ASKPASS", std::env::current_exe()?)
+        .env("SSH_ASKPASS_REQUIRE", "force")
+        .env(ASKPASS_ENV, "1")
+        .env(ASKPASS_OLD_ENV, old)
+        .env(ASKPASS_NEW_ENV, new)
+        .stdin(Stdio::null())
+        .output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Answers ssh-keygen's passphrase prompt when tilb was started as its askpass
+/// helper by `change_key_passphrase`, returning whether it was
+pub fn answer_askpass() -> bool {
+    if std::env::var_os(ASKPASS_ENV).is_none() {
+        return false;
+    }
+
+    // e.g. "Enter old passphrase: ", "Enter same passphrase again: "
+    let prompt = std::env::args().nth(1).unwrap_or_default();
+    let answer = if prompt.to_lowercase().contains("old passphrase") {
+        ASKPASS_OLD_ENV
+    } else {
+        ASKPASS_NEW_ENV
+    };
+    println!("{}", std::env::var(answer).unwrap_or_default());
+    true
+}
+
+/// Writes a file only its owner can read, as ssh insists on for private keys.
+/// Keys never go through changes.rs, so they don't end up in the journal or a dry run diff
+pub fn write_secret_file(path: &Path, content: &str) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content.as_bytes())?;
+
+    // the mode above only applies to new files
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+const BLOCK_MARKER: &str = "#tilb generated";
+
+/// Options tilb writes itself, lowercased, `ssh_options` can't override them
+const RESERVED_SSH_OPTIONS: [&str; 5] =
+    ["host", "hostname", "user", "identityfile", "identitiesonly"];
+
+/// Whether `key value` can go in a generated block as a line of its own. Anything
+/// else could end the block early, e.g. a value with a newline and `Host *` after it
+pub fn check_ssh_option(key: &str, value: &str) -> Result<(), String> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!(
+            "\"{}\" isn't an ssh option name",
+            key.escape_debug()
+        ));
+    }
+    if RESERVED_SSH_OPTIONS.contains(&key.to_lowercase().as_str()) {
+        return Err(format!("ssh option {} is set by tilb", key));
+    }
+    if value.trim().is_empty() || value.contains(['\n', '\r']) {
+        return Err(format!("ssh option {} needs a value on a single line", key));
+    }
+    Ok(())
+}
+
+/// Where the generated block for `host_alias` starts and ends in `lines`
+fn find_generated_block(lines: &[String], host_alias: &str) -> Option<Range<usize>> {
+    let block_header = format!("Host {}", host_alias);
+
+    let start = lines.iter().enumerate().position(|(i, line)| {
+        line.trim() == BLOCK_MARKER && lines.get(i + 1).is_some_and(|n| n.trim() == block_header)
+    })?;
+
+    // the block ends at the next generated block or user-defined Host/Match
+    let mut end = start + 2; // skipping first host line
+    while end < lines.len() {
+        let line = &lines[end];
+        if line.trim() == BLOCK_MARKER || line.starts_with("Host ") || line.starts_with("Match ") {
+            break;
+        }
+        end += 1;
+    }
+    // blank lines and comments above the next Host belong to that one
+    while end > start + 2 && {
+        let line = lines[end - 1].trim();
+        line.is_empty() || line.starts_with('#')
+    } {
+        end -= 1;
+    }
+    Some(start..end)
+}
+
+/// Takes out the lines in `range`, and the blank line that separated them from the rest
+fn remove_block(lines: &mut Vec<String>, range: Range<usize>) {
+    let start = range.start;
+    lines.splice(range, std::iter::empty());
+    if lines.get(start).is_some_and(|l| l.trim().is_empty())
+        && (start == 0 || lines[start - 1].trim().is_empty())
+    {
+        lines.remove(start);
+    }
+}
+
+/// Whether a `Host` line (patterns, `!` negations) applies to `host`, as ssh matches them
+fn host_line_matches(line: &str, host: &str) -> bool {
+    let mut words = line.split(|c: char| c.is_whitespace() || c == '=');
+    if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("host")) {
+        return false;
+    }
+
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..MatchOptions::new()
+    };
+    let matches =
+        |pattern: &str| Pattern::new(pattern).is_ok_and(|p| p.matches_with(host, options));
+
+    let mut matched = false;
+    for pattern in words.filter(|w| !w.is_empty()) {
+        match pattern.strip_prefix('!') {
+            Some(negated) if matches(negated) => return false,
+            Some(_) => {}
+            None => matched |= matches(pattern),
+        }
+    }
+    matched
+}
+
+/// Puts `block` in place of the generated block for `host_alias`. ssh goes with the
+/// first value it finds, so the block goes above any Host the user wrote that also
+/// matches, e.g. an existing `Host github.com` when switching globally
+fn place_block(lines: &mut Vec<String>, host_alias: &str, block: Vec<String>) {
+    let existing = find_generated_block(lines, host_alias);
+    let shadowing = lines
+        .iter()
+        .position(|line| host_line_matches(line.trim(), host_alias))
+        .filter(|&i| existing.as_ref().is_none_or(|block| i < block.start));
+
+    match (existing, shadowing) {
+        // replace existing block
+        (Some(range), None) => {
+            lines.splice(range, block);
+        }
+        (existing, Some(i)) => {
+            // the old block is below the user's Host, so removing it leaves `i` alone
+            if let Some(range) = existing {
+                remove_block(lines, range);
+            }
+            // keep comments right above the user's Host with it
+            let mut at = i;
+            while at > 0
+                && lines[at - 1].trim_start().starts_with('#')
+                && lines[at - 1].trim() != BLOCK_MARKER
+            {
+                at -= 1;
+            }
+            lines.splice(at..at, block.into_iter().chain([String::new()]));
+        }
+        // append new block at the end
+        (None, None) => {
+            while lines.last().is_some_and(|l| l.trim().is_empty()) {
+                lines.pop();
+            }
+            if !lines.is_empty() {
+                lines.push("".into()); // blank line before appending
+            }
+            lines.extend(block);
+        }
+    }
+}
+
 pub fn add_to_ssh_config(
     host_alias: &str,
     host_name: &str,
     user: &str,
     identity_file: &str,
+    options: &BTreeMap<String, String>,
 ) -> Result<(), std::io::Error> {
     if !ssh_config_exists() {
         create_ssh_config()?;
     }
 
-    let path = get_ssh_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let path = get_ssh_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
-    let mut lines: Vec<String> = fs::read_to_string(&path)
+    let mut lines: Vec<String> = changes::read_to_string(&path)
         .unwrap_or_default()
         .lines()
         .map(|l| l.to_string())
         .collect();
 
-    let block_marker = "#tilb generated";
-    let block_header = format!("Host {}", host_alias);
+    for (key, value) in options {
+        check_ssh_option(key, value)
+            .map_err(|problem| Error::new(ErrorKind::InvalidInput, problem))?;
+    }
 
     // new block we want to ensure
-    let new_block = vec![
-        block_marker.to_string(),
-        block_header.clone(),
+    let mut new_block = vec![
+        BLOCK_MARKER.to_string(),
+        format!("Host {}", host_alias),
         format!("  HostName {}", host_name),
         format!("  User {}", user),
         format!("  IdentityFile {}", identity_file),
+        // don't let keys already loaded in the agent log in as someone else
+        "  IdentitiesOnly yes".to_string(),
     ];
-
-    // search for an existing generated block with same host
-    let mut start = None;
-    for (i, line) in lines.iter().enumerate() {
-        if line.trim() == block_marker {
-            if let Some(next) = lines.get(i + 1) {
-                if next.trim() == block_header {
-                    start = Some(i);
-                    break;
-                }
-            }
-        }
+    for (key, value) in options {
+        new_block.push(format!("  {} {}", key, value));
     }
 
-    if let Some(idx) = start {
-        // replace existing block
-        // find where this block ends (next comment or end of file)
-        let mut end = idx + 2; // skipping first host line
-        while end < lines.len() {
-            if lines[end].trim() == block_marker {
-                break; // another generated block
-            }
-            // stop when encountering a non-indented host (user-defined)
-            if lines[end].starts_with("Host ") {
-                break;
-            }
-            end += 1;
-        }
-        lines.splice(idx..end, new_block);
-    } else {
-        // append new block at the end
-        if !lines.is_empty() {
-            lines.push("".into()); // blank line before appending
-        }
-        lines.extend(new_block);
-    }
+    place_block(&mut lines, host_alias, new_block);
+
+    changes::write(&path, &(lines.join("\n") + "\n"))?;
+    Ok(())
+}
 
-    fs::write(path, lines.join("\n") + "\n")?;
-    return Ok(());
+/// Options in the generated block for `host_alias` beyond the ones tilb writes,
+/// e.g. a Port added by hand
+pub fn get_block_options(host_alias: &str) -> BTreeMap<String, String> {
+    let Some(path) = get_ssh_config_path() else {
+        return BTreeMap::new();
+    };
+    let lines: Vec<String> = changes::read_to_string(&path)
+        .unwrap_or_default()
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+    block_options(&lines, host_alias)
+}
+
+fn block_options(lines: &[String], host_alias: &str) -> BTreeMap<String, String> {
+    let Some(range) = find_generated_block(lines, host_alias) else {
+        return BTreeMap::new();
+    };
+
+    lines[range]
+        .iter()
+        .skip(2) // the marker and Host line
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (key, value) = line.split_once(|c: char| c.is_whitespace() || c == '=')?;
+            let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+            let value = value.trim();
+            check_ssh_option(key, value)
+                .is_ok()
+                .then(|| (key.to_string(), value.to_string()))
+        })
+        .collect()
 }
 
 pub fn remove_from_ssh_config(host_alias: &str) -> Result<(), std::io::Error> {
@@ -168,45 +453,17 @@ pub fn remove_from_ssh_config(host_alias: &str) -> Result<(), std::io::Error> {
         return Ok(());
     }
 
-    let path = get_ssh_config_path().ok_or_else(|| Error::new(ErrorKind::Other, "no home dir"))?;
+    let path = get_ssh_config_path().ok_or_else(|| Error::other("no home dir"))?;
 
-    let mut lines: Vec<String> = fs::read_to_string(&path)?
+    let mut lines: Vec<String> = changes::read_to_string(&path)?
         .lines()
         .map(|l| l.to_string())
         .collect();
 
-    let block_marker = "#tilb generated";
-    let block_header = format!("Host {}", host_alias);
-
-    let mut start = None;
-    for (i, line) in lines.iter().enumerate() {
-        if line.trim() == block_marker {
-            if let Some(next) = lines.get(i + 1) {
-                if next.trim() == block_header {
-                    start = Some(i);
-                    break;
-                }
-            }
-        }
-    }
+    if let Some(range) = find_generated_block(&lines, host_alias) {
+        remove_block(&mut lines, range);
 
-    if let Some(idx) = start {
-        // replace existing block
-        // find where this block ends (next comment or end of file)
-        let mut end = idx + 2; // skipping first host line
-        while end < lines.len() {
-            if lines[end].trim() == block_marker {
-                break; // another generated block
-            }
-            // stop when encountering a non-indented host (user-defined)
-            if lines[end].starts_with("Host ") {
-                break;
-            }
-            end += 1;
-        }
-        lines.splice(idx..end, std::iter::empty());
-
-        fs::write(path, lines.join("\n").trim_end().to_string() + "\n")?;
+        changes::write(&path, &(lines.join("\n").trim_end().to_string() + "\n"))?;
         info!("Removed generated ssh block for '{}'.", host_alias);
     } else {
         eprintln!(
@@ -215,5 +472,253 @@ pub fn remove_from_ssh_config(host_alias: &str) -> Result<(), std::io::Error> {
         );
     }
 
-    return Ok(());
+    Ok(())
+}
+
+/// Who a host says we logged in as, from what `ssh -T` prints
+#[derive(Debug, PartialEq, Eq)]
+pub enum Greeting {
+    /// The account the key belongs to
+    Account(String),
+    /// A key registered to a single repo, e.g. a github deploy key
+    DeployKey(String),
+    /// The host turned the key down
+    Denied,
+}
+
+/*
+what hosts print for `ssh -T git@host`, all end up as the account name:
+
+github            Hi octocat! You've successfully authenticated, but GitHub does not provide shell access.
+gitlab            Welcome to GitLab, @octocat!
+gitea, forgejo    Hi there, octocat! You've successfully authenticated with the key named ...
+bitbucket         authenticated via ssh key. ... logged in as octocat.
+sourcehut         Hi octocat! You've successfully authenticated, but I do not provide an interactive shell.
+
+github deploy keys greet with owner/repo instead
+*/
+
+pub fn parse_greeting(output: &str) -> Option<Greeting> {
+    let patterns = [
+        r"Hi (?:there, )?([^!\s]+)! You've successfully authenticated",
+        r"Welcome to GitLab, @([^!\s]+)!",
+        r"logged in as ([^\s]+?)\.?(?:\s|$)",
+    ];
+
+    for pattern in patterns {
+        let re = Regex::new(pattern).unwrap();
+        if let Some(captures) = re.captures(output) {
+            let account = captures[1].to_string();
+            if account.contains('/') {
+                return Some(Greeting::DeployKey(account));
+            }
+            return Some(Greeting::Account(account));
+        }
+    }
+
+    if output.contains("Permission denied") {
+        return Some(Greeting::Denied);
+    }
+    None
+}
+
+/// Everything `ssh -T git@<host_alias>` prints, hosts differ in which stream they use
+pub fn ssh_greeting(host_alias: &str) -> Result<String, Error> {
+    // ssh asks for a passphrase or a new host key on the terminal, not stdin
+    let output = Command::new("ssh")
+        .arg("-T")
+        .arg("-o")
+        .arg("ConnectTimeout=10")
+        .arg(format!("git@{}", host_alias))
+        .stdin(Stdio::null())
+        .output()?;
+
+    // most hosts exit 1 here since there's no shell, so the status says nothing
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(|l| l.to_string()).collect()
+    }
+
+    fn block(alias: &str) -> Vec<String> {
+        lines(&format!(
+            "{}\nHost {}\n  HostName github.com\n  User git",
+            BLOCK_MARKER, alias
+        ))
+    }
+
+    #[test]
+    fn host_line_patterns() {
+        assert!(host_line_matches("Host github.com", "github.com"));
+        assert!(host_line_matches(
+            "host gitlab.com GitHub.com",
+            "github.com"
+        ));
+        assert!(host_line_matches("Host=*.com", "github.com"));
+        assert!(host_line_matches("Host *", "github.com"));
+        assert!(!host_line_matches("Host * !github.com", "github.com"));
+        assert!(!host_line_matches("Host github.com-work", "github.com"));
+        assert!(!host_line_matches("  HostName github.com", "github.com"));
+        assert!(!host_line_matches("Match host github.com", "github.com"));
+    }
+
+    #[test]
+    fn block_is_appended_when_nothing_matches() {
+        let mut config = lines("Host example.com\n  User me\n\n");
+        place_block(&mut config, "tilb-me", block("tilb-me"));
+        assert_eq!(config[..3], lines("Host example.com\n  User me\n\n")[..]);
+        assert_eq!(config[3..], block("tilb-me")[..]);
+    }
+
+    #[test]
+    fn block_goes_above_a_users_host() {
+        let mut config = lines("# work\nHost github.com\n  IdentityFile ~/.ssh/work");
+        place_block(&mut config, "github.com", block("github.com"));
+        let mut expected = block("github.com");
+        expected.extend(lines(
+            "\n# work\nHost github.com\n  IdentityFile ~/.ssh/work",
+        ));
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn shadowed_block_moves_up() {
+        let mut config = lines("Host github.com\n  User me\n\n");
+        config.extend(block("github.com"));
+        config.push("  IdentityFile old".to_string());
+        place_block(&mut config, "github.com", block("github.com"));
+        let mut expected = block("github.com");
+        expected.extend(lines("\nHost github.com\n  User me\n\n"));
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn ssh_options_stay_on_their_line() {
+        assert!(check_ssh_option("Port", "443").is_ok());
+        assert!(check_ssh_option("ProxyCommand", "ssh -W %h:%p bastion").is_ok());
+        assert!(check_ssh_option("Port", "22\nHost *").is_err());
+        assert!(check_ssh_option("Port", "22\r").is_err());
+        assert!(check_ssh_option("Port", " ").is_err());
+        assert!(check_ssh_option("Port 22\nMatch all", "x").is_err());
+        assert!(check_ssh_option("Proxy Command", "x").is_err());
+        assert!(check_ssh_option("", "x").is_err());
+        assert!(check_ssh_option("hostname", "evil.com").is_err());
+    }
+
+    #[test]
+    fn options_added_by_hand() {
+        let mut config = block("tilb-me");
+        config.extend(lines(
+            "  IdentitiesOnly yes\n  Port 443\n  ProxyJump=bastion\n\nHost x\n  Port 1",
+        ));
+        let options = block_options(&config, "tilb-me");
+        assert_eq!(
+            options.into_iter().collect::<Vec<_>>(),
+            [
+                ("Port".to_string(), "443".to_string()),
+                ("ProxyJump".to_string(), "bastion".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn block_is_replaced_in_place() {
+        let mut config = block("tilb-me");
+        config.push("  Port 22".to_string());
+        config.extend(lines("Host other\n  User x"));
+        place_block(&mut config, "tilb-me", block("tilb-me"));
+        let mut expected = block("tilb-me");
+        expected.extend(lines("Host other\n  User x"));
+        assert_eq!(config, expected);
+    }
+
+    fn account(name: &str) -> Option<Greeting> {
+        Some(Greeting::Account(name.to_string()))
+    }
+
+    #[test]
+    fn github_greeting() {
+        assert_eq!(
+            parse_greeting(
+                "Hi octocat! You've successfully authenticated, but GitHub does not provide shell access.\n"
+            ),
+            account("octocat")
+        );
+        // ssh's own chatter comes first on a new host
+        assert_eq!(
+            parse_greeting(
+                "Warning: Permanently added 'github.com' (ED25519) to the list of known hosts.\r\n\
+                 Hi octo-cat! You've successfully authenticated, but GitHub does not provide shell access.\n"
+            ),
+            account("octo-cat")
+        );
+    }
+
+    #[test]
+    fn gitlab_greeting() {
+        assert_eq!(
+            parse_greeting("Welcome to GitLab, @octocat!\n"),
+            account("octocat")
+        );
+    }
+
+    #[test]
+    fn gitea_greeting() {
+        assert_eq!(
+            parse_greeting(
+                "Hi there, octocat! You've successfully authenticated with the key named tilb octocat, \
+                 but Gitea does not provide shell access.\n\
+                 If this is unexpected, please log in with password and setup Gitea under another user.\n"
+            ),
+            account("octocat")
+        );
+    }
+
+    #[test]
+    fn bitbucket_greeting() {
+        assert_eq!(
+            parse_greeting(
+                "logged in as octocat.\n\n\
+                 You can use git or hg to connect to Bitbucket. Shell access is disabled.\n"
+            ),
+            account("octocat")
+        );
+    }
+
+    #[test]
+    fn deploy_key_greeting() {
+        assert_eq!(
+            parse_greeting(
+                "Hi octocat/hello-world! You've successfully authenticated, but GitHub does not provide shell access.\n"
+            ),
+            Some(Greeting::DeployKey("octocat/hello-world".to_string()))
+        );
+    }
+
+    #[test]
+    fn denied_greeting() {
+        assert_eq!(
+            parse_greeting("git@github.com: Permission denied (publickey).\r\n"),
+            Some(Greeting::Denied)
+        );
+    }
+
+    #[test]
+    fn unknown_banner() {
+        assert_eq!(parse_greeting("Welcome to the machine.\n"), None);
+        assert_eq!(parse_greeting(""), None);
+        assert_eq!(
+            parse_greeting(
+                "ssh: Could not resolve hostname tilb-octocat: Name or service not known\r\n"
+            ),
+            None
+        );
+    }
 }
@@ -0,0 +1,59 @@
+use std::io::{Error, ErrorKind, Result};
+
+use serde::{Deserialize, Serialize};
+
+use crate::changes;
+use crate::paths::get_state_path;
+
+/*
+things tilb needs to remember between runs that aren't user settings,
+kept apart from config.toml so hand edits there never clash with it
+*/
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(default)]
+    pub global: GlobalState,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GlobalState {
+    pub current: Option<GlobalIdentity>,
+    pub previous: Option<GlobalIdentity>,
+}
+
+/// A machine-wide identity, either set by tilb or found in the global git config
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GlobalIdentity {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// committer.name, when it differs from the author's
+    #[serde(default)]
+    pub committer_name: Option<String>,
+    /// committer.email, when it differs from the author's
+    #[serde(default)]
+    pub committer_email: Option<String>,
+    /// The tilb user this identity came from, if any
+    pub profile: Option<String>,
+    /// The host tilb wrote a default ssh block for, if any
+    pub ssh_host: Option<String>,
+}
+
+pub fn read_state_file() -> Result<State> {
+    let path = get_state_path().ok_or_else(|| Error::other("no home dir"))?;
+
+    let content = match changes::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(State::default()),
+        Err(err) => return Err(err),
+    };
+
+    toml::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+pub fn write_state_file(state: &State) -> Result<()> {
+    let path = get_state_path().ok_or_else(|| Error::other("no home dir"))?;
+
+    let toml_str = toml::to_string_pretty(state).map_err(Error::other)?;
+    changes::write(&path, &toml_str) // also creates the tilb home
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::config::{Rule, User};
+use crate::rules::expand_home;
+use crate::ssh::check_ssh_option;
+
+/*
+templates let a team share one setup for a kind of profile, e.g. from a
+dotfiles checkout. config.toml points at a file, or at a directory whose
+*.toml files are all read:
+
+templates = "~/src/dotfiles/tilb"
+
+each table in a template file is one template:
+
+[acme]
+git_host = "github.acme.com"
+sign_commits = true
+default_branch = "main"
+ssh_options = { Port = "2222" }
+
+[[acme.rules]]
+path = "~/src/acme"
+
+`add --template acme` only asks for the username and email, and
+`template sync` puts the template's values back on users made from it
+*/
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub git_host: String,
+    #[serde(default)]
+    pub use_https: bool,
+    #[serde(default)]
+    pub sign_commits: bool,
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    #[serde(default)]
+    pub ssh_options: BTreeMap<String, String>,
+    /// Guard rules added for every user made from the template
+    #[serde(default)]
+    pub rules: Vec<TemplateRule>,
+}
+
+/// A `Rule` without the user, which is filled in per user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateRule {
+    #[serde(default)]
+    pub remote: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadedTemplate {
+    pub template: Template,
+    /// The file it was read from
+    pub source: PathBuf,
+}
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), message),
+    )
+}
+
+fn read_template_file(path: &Path, templates: &mut BTreeMap<String, LoadedTemplate>) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let parsed: BTreeMap<String, Template> =
+        toml::from_str(&content).map_err(|e| invalid(path, e.message().trim_end()))?;
+
+    for (name, template) in parsed {
+        for (key, value) in &template.ssh_options {
+            if let Err(problem) = check_ssh_option(key, value) {
+                return Err(invalid(path, format!("{}: {}", name, problem)));
+            }
+        }
+        if template
+            .rules
+            .iter()
+            .any(|rule| rule.remote.is_none() && rule.path.is_none())
+        {
+            return Err(invalid(
+                path,
+                format!("{}: every rule needs a remote or a path", name),
+            ));
+        }
+        if let Some(existing) = templates.get(&name) {
+            return Err(invalid(
+                path,
+                format!(
+                    "template {} is also defined in {}",
+                    name,
+                    existing.source.display()
+                ),
+            ));
+        }
+        templates.insert(
+            name,
+            LoadedTemplate {
+                template,
+                source: path.to_path_buf(),
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Reads every template under `location`, a file or a directory of *.toml files
+pub fn load_templates(location: &str) -> Result<BTreeMap<String, LoadedTemplate>> {
+    let path = PathBuf::from(expand_home(location));
+    let mut templates = BTreeMap::new();
+
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        files.sort();
+        for file in files {
+            read_template_file(&file, &mut templates)?;
+        }
+    } else {
+        read_template_file(&path, &mut templates)?;
+    }
+
+    Ok(templates)
+}
+
+impl Template {
+    /// Overwrites everything on `user` the template decides
+    pub fn apply_to(&self, user: &mut User) {
+        user.git_host = self.git_host.clone();
+        user.use_https = self.use_https;
+        user.sign_commits = self.sign_commits && !self.use_https;
+        user.default_branch = self.default_branch.clone();
+        user.ssh_options = self.ssh_options.clone();
+    }
+
+    /// The template's rules bound to `user`
+    pub fn rules_for(&self, name: &str, user: &str) -> Vec<Rule> {
+        self.rules
+            .iter()
+            .map(|rule| Rule {
+                remote: rule.remote.clone(),
+                path: rule.path.clone(),
+                user: user.to_string(),
+                template: Some(name.to_string()),
+            })
+            .collect()
+    }
+}
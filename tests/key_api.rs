@@ -0,0 +1,413 @@
+// runs `tilb key upload` and `tilb key list` through --api-url against a
+// small in-process imitation of the hosts' ssh key endpoints
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Command, Output},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde_json::{Value, json};
+
+const TOKEN: &str = "sekrit";
+const PUBLIC_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIFakeKeyForTests me@laptop";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Host {
+    Github,
+    Gitlab,
+    Gitea,
+}
+
+#[derive(Default)]
+struct Account {
+    auth_keys: Vec<Value>,
+    signing_keys: Vec<Value>,
+    next_id: u64,
+    requests: Vec<String>,
+    /// A gitlab usage_type the host fails to add keys for
+    refused_usage: Option<&'static str>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<String>,
+    body: String,
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).ok()?;
+        let header = header.trim_end().to_string();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().ok()?;
+        }
+        headers.push(header);
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+fn key_body(key: &Value) -> String {
+    let key = key["key"].as_str().unwrap_or_default();
+    key.split_whitespace().take(2).collect::<Vec<_>>().join(" ")
+}
+
+/// What the host answers, roughly how github, gitlab and gitea behave
+fn respond(host: Host, account: &mut Account, request: &Request) -> (u16, Value) {
+    let gitlab = host == Host::Gitlab;
+    let auth = match host {
+        Host::Github => format!("authorization: bearer {}", TOKEN),
+        Host::Gitlab => format!("private-token: {}", TOKEN),
+        Host::Gitea => format!("authorization: token {}", TOKEN),
+    };
+    if !request
+        .headers
+        .iter()
+        .any(|header| header.eq_ignore_ascii_case(&auth))
+    {
+        return (401, json!({ "message": "401 Unauthorized" }));
+    }
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    let keys = match path {
+        "/user/ssh_signing_keys" if host == Host::Github => &mut account.signing_keys,
+        _ => &mut account.auth_keys,
+    };
+
+    match (request.method.as_str(), path) {
+        ("GET", "/user/keys" | "/user/ssh_signing_keys") => (200, Value::Array(keys.clone())),
+        ("POST", "/user/keys" | "/user/ssh_signing_keys") => {
+            let mut key: Value = serde_json::from_str(&request.body).unwrap();
+            if key["usage_type"]
+                .as_str()
+                .is_some_and(|usage| Some(usage) == account.refused_usage)
+            {
+                return (500, json!({ "message": "500 Internal Server Error" }));
+            }
+            if keys.iter().any(|other| key_body(other) == key_body(&key)) {
+                return if gitlab {
+                    (
+                        400,
+                        json!({ "message": { "key": ["has already been taken"] } }),
+                    )
+                } else {
+                    (422, json!({ "message": "key is already in use" }))
+                };
+            }
+            account.next_id += 1;
+            key["id"] = json!(account.next_id);
+            keys.push(key.clone());
+            (201, key)
+        }
+        ("DELETE", _) if gitlab => {
+            let id = path.trim_start_matches("/user/keys/");
+            let before = keys.len();
+            keys.retain(|key| key["id"] != id.parse::<u64>().unwrap_or_default());
+            if keys.len() == before {
+                (404, json!({ "message": "404 Not found" }))
+            } else {
+                (204, Value::Null)
+            }
+        }
+        _ => (404, json!({ "message": "404 Not found" })),
+    }
+}
+
+/// Serves one account until the test ends, returning its url and state
+fn serve(host: Host) -> (String, Arc<Mutex<Account>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let account = Arc::new(Mutex::new(Account::default()));
+
+    let state = Arc::clone(&account);
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let Some(request) = read_request(&stream) else {
+                continue;
+            };
+            let mut account = state.lock().unwrap();
+            account
+                .requests
+                .push(format!("{} {}", request.method, request.path));
+            let (status, body) = respond(host, &mut account, &request);
+            let body = if body.is_null() {
+                String::new()
+            } else {
+                body.to_string()
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+    (url, account)
+}
+
+/// A home with one user `me`, whose key is `PUBLIC_KEY`
+struct Home {
+    dir: PathBuf,
+}
+
+impl Home {
+    fn new(name: &str, sign_commits: bool) -> Home {
+        let dir = std::env::temp_dir().join(format!("tilb-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("tilb")).unwrap();
+        fs::create_dir_all(dir.join("ssh")).unwrap();
+        fs::write(
+            dir.join("ssh/id_me_ed25519.pub"),
+            format!("{}\n", PUBLIC_KEY),
+        )
+        .unwrap();
+        let home = Home { dir };
+        home.set_signing(sign_commits);
+        home
+    }
+
+    fn set_signing(&self, sign_commits: bool) {
+        fs::write(
+            self.dir.join("tilb/config.toml"),
+            format!(
+                "version = 2\n\n[users.me]\nusername = \"me\"\nemail = \"me@example.com\"\ngit_host = \"git.example.com\"\nuse_https = false\nsign_commits = {}\n",
+                sign_commits
+            ),
+        )
+        .unwrap();
+    }
+
+    fn tilb(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_tilb"))
+            .args(args)
+            .env("HOME", &self.dir)
+            .env("TILB_HOME", self.dir.join("tilb"))
+            .env("TILB_SSH_DIR", self.dir.join("ssh"))
+            .env("TILB_TOKEN", TOKEN)
+            .env_remove("XDG_CONFIG_HOME")
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for Home {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "tilb failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // tilb colours its output whether or not it's a terminal
+    let mut plain = String::new();
+    let mut chars = String::from_utf8_lossy(&output.stdout).into_owned();
+    while let Some(start) = chars.find('\x1b') {
+        plain.push_str(&chars[..start]);
+        let end = chars[start..]
+            .find('m')
+            .map_or(chars.len(), |end| start + end + 1);
+        chars = chars[end..].to_string();
+    }
+    plain.push_str(&chars);
+    plain
+}
+
+#[test]
+fn github_upload_and_list() {
+    let (url, account) = serve(Host::Github);
+    let home = Home::new("github", true);
+    let remote = ["--provider", "github", "--api-url", url.as_str()];
+
+    let upload = [&["key", "upload", "me"], &remote[..]].concat();
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("as auth and signing key"), "{}", out);
+    {
+        let account = account.lock().unwrap();
+        assert_eq!(account.auth_keys.len(), 1);
+        assert_eq!(account.signing_keys.len(), 1);
+        assert_eq!(account.auth_keys[0]["title"], "tilb me");
+    }
+
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("already on the GitHub account"), "{}", out);
+
+    let list = [&["key", "list", "me"], &remote[..]].concat();
+    let out = stdout(&home.tilb(&list));
+    assert!(
+        out.contains("1  tilb me (auth)  <- this user's key"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("2  tilb me (signing)  <- this user's key"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn gitlab_adds_signing_to_an_auth_key() {
+    let (url, account) = serve(Host::Gitlab);
+    let home = Home::new("gitlab", false);
+    let remote = ["--provider", "gitlab", "--api-url", url.as_str()];
+    let upload = [&["key", "upload", "me"], &remote[..]].concat();
+
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("as auth key"), "{}", out);
+    assert_eq!(account.lock().unwrap().auth_keys[0]["usage_type"], "auth");
+
+    // gitlab refuses the same key twice, so it's replaced by one for both
+    home.set_signing(true);
+    account.lock().unwrap().requests.clear();
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("as signing key"), "{}", out);
+    {
+        let account = account.lock().unwrap();
+        assert_eq!(account.auth_keys.len(), 1);
+        assert_eq!(account.auth_keys[0]["usage_type"], "auth_and_signing");
+        assert_eq!(account.auth_keys[0]["title"], "tilb me");
+        assert!(
+            account
+                .requests
+                .iter()
+                .any(|request| request == "DELETE /user/keys/1"),
+            "{:?}",
+            account.requests
+        );
+    }
+
+    let list = [&["key", "list", "me"], &remote[..]].concat();
+    let out = stdout(&home.tilb(&list));
+    assert!(
+        out.contains("2  tilb me (auth, signing)  <- this user's key"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn bad_token_fails() {
+    let (url, _account) = serve(Host::Gitlab);
+    let home = Home::new("token", false);
+    let output = Command::new(env!("CARGO_BIN_EXE_tilb"))
+        .args([
+            "key",
+            "list",
+            "me",
+            "--provider",
+            "gitlab",
+            "--api-url",
+            &url,
+        ])
+        .env("HOME", &home.dir)
+        .env("TILB_HOME", home.dir.join("tilb"))
+        .env("TILB_SSH_DIR", home.dir.join("ssh"))
+        .env("TILB_TOKEN", "wrong")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("returned 401: 401 Unauthorized"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn gitlab_puts_the_old_key_back_when_replacing_fails() {
+    let (url, account) = serve(Host::Gitlab);
+    let home = Home::new("gitlab-restore", false);
+    let upload = [
+        "key",
+        "upload",
+        "me",
+        "--provider",
+        "gitlab",
+        "--api-url",
+        url.as_str(),
+    ];
+    stdout(&home.tilb(&upload));
+
+    home.set_signing(true);
+    account.lock().unwrap().refused_usage = Some("auth_and_signing");
+    let output = home.tilb(&upload);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the old key was put back"), "{}", stderr);
+
+    let account = account.lock().unwrap();
+    assert_eq!(account.auth_keys.len(), 1);
+    assert_eq!(account.auth_keys[0]["usage_type"], "auth");
+    assert_eq!(account.auth_keys[0]["title"], "tilb me");
+}
+
+#[test]
+fn gitea_upload_and_list() {
+    let (url, account) = serve(Host::Gitea);
+    let home = Home::new("gitea", true);
+    let remote = ["--provider", "gitea", "--api-url", url.as_str()];
+
+    // gitea keys are for both, so one upload covers signing too
+    let upload = [&["key", "upload", "me"], &remote[..]].concat();
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("as auth and signing key"), "{}", out);
+    {
+        let account = account.lock().unwrap();
+        assert_eq!(account.auth_keys.len(), 1);
+        assert_eq!(account.auth_keys[0]["title"], "tilb me");
+        assert!(
+            account
+                .requests
+                .iter()
+                .any(|request| request == "GET /user/keys?limit=100"),
+            "{:?}",
+            account.requests
+        );
+    }
+
+    let out = stdout(&home.tilb(&upload));
+    assert!(out.contains("already on the Gitea account"), "{}", out);
+
+    let list = [&["key", "list", "me"], &remote[..]].concat();
+    let out = stdout(&home.tilb(&list));
+    assert!(
+        out.contains("1  tilb me (auth, signing)  <- this user's key"),
+        "{}",
+        out
+    );
+}